@@ -0,0 +1,109 @@
+//! saving the raw accumulation buffer of an image so renders can be continued or combined
+//!
+//! An `.acc` file holds everything `Image` accumulates before tone mapping: the summed color of every
+//! pixel, how many samples hit it and how many samples were drawn in total. Renders of the same config
+//! made in several sessions, or on several machines, can be merged and tone mapped as one:
+//!
+//! ```sh
+//! barnsley merge a.acc b.acc -o out.png
+//! ```
+//!
+//! The format is a small header followed by the buffers, all little-endian:
+//! the magic bytes `BARNSACC`, a `u32` format version, the `u32` width and height, the `u64` sample total,
+//! then `width * height * 3` `f32` colors and `width * height` `u32` hit counts, both in `[x, y]` order.
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use ndarray::{Array2, Array3};
+use crate::image::Image;
+
+const MAGIC: &[u8; 8] = b"BARNSACC";
+/// Version of the `.acc` layout, raised whenever it changes
+pub const ACCUMULATION_VERSION: u32 = 1;
+const HEADER_LEN: usize = 8 + 4 + 4 + 4 + 8;
+
+impl Image {
+    /// Write the raw accumulation buffer to `path`, see the module documentation for the layout
+    pub fn save_accumulation(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        let mut file = BufWriter::new(file);
+        let mut write = || -> std::io::Result<()> {
+            file.write_all(MAGIC)?;
+            file.write_all(&ACCUMULATION_VERSION.to_le_bytes())?;
+            file.write_all(&(self.width() as u32).to_le_bytes())?;
+            file.write_all(&(self.height() as u32).to_le_bytes())?;
+            file.write_all(&self.samples().to_le_bytes())?;
+            for value in self.data().iter() {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            for count in self.hits().iter() {
+                file.write_all(&count.to_le_bytes())?;
+            }
+            file.flush()
+        };
+        write().map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+
+    /// Read an image back from an accumulation buffer written by `save_accumulation`
+    pub fn load_accumulation(path: &Path) -> Result<Image, String> {
+        let bytes = fs::read(path).map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+        if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+            return Err(format!("{} is not a barnsley accumulation buffer", path.display()));
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let version = u32_at(8);
+        if version > ACCUMULATION_VERSION {
+            return Err(format!("{} has version {} but the newest supported version is {}",
+                               path.display(), version, ACCUMULATION_VERSION));
+        }
+        let (width, height) = (u32_at(12) as usize, u32_at(16) as usize);
+        let samples = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+
+        let body = &bytes[HEADER_LEN..];
+        if body.len() != width * height * 4 * 4 {
+            return Err(format!("{} should hold a {}x{} buffer but has the wrong length", path.display(), width, height));
+        }
+        let (colors, counts) = body.split_at(width * height * 3 * 4);
+        let data = colors.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        let hits = counts.chunks_exact(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect();
+        Ok(Image::from_parts(
+            Array3::from_shape_vec((width, height, 3), data).unwrap(),
+            Array2::from_shape_vec((width, height), hits).unwrap(),
+            samples
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::Image;
+    use crate::util::{test_path, Color};
+
+    #[test]
+    fn test_accumulation_round_trip_and_merge() {
+        let path = test_path("accumulation.acc");
+        let mut image = Image::new(5, 3);
+        image.add_radiance(4, 1, Color{r: 0.5, g: 0.25, b: 1.0});
+        image.add_samples(30);
+        image.save_accumulation(&path).unwrap();
+
+        let mut loaded = Image::load_accumulation(&path).unwrap();
+        assert_eq!(loaded.data(), image.data());
+        assert_eq!(loaded.hits(), image.hits());
+        assert_eq!(loaded.samples(), 30);
+
+        loaded.merge(&image);
+        assert_eq!(loaded.data()[[4, 1, 2]], 2.0);
+        assert_eq!(loaded.hits()[[4, 1]], 2);
+        assert_eq!(loaded.samples_per_pixel(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let path = test_path("not_accumulation.acc");
+        std::fs::write(&path, b"not an accumulation buffer at all").unwrap();
+        assert!(Image::load_accumulation(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
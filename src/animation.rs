@@ -6,37 +6,184 @@
 //! and `step_counts=vec![100, 200];` It would take 100 steps interpolating between `a` and `b` 
 //! and `200` steps between `b` and `c`. 
 //! 
-use crate::{ifs::IFS, image::Image};
+//! Animations can also be described in a json file, an `AnimationConfig`, and rendered 
+//! with `barnsley animate`. 
+//! ```json
+//! {
+//!     "image_settings": {"width": 500, "height": 500, "path": "frames"},
+//!     "evaluation_settings": {"num_iterations": 1000, "num_points": 1000},
+//!     "keyframes": ["start.json", {"transforms": [...]}, "end.json"],
+//!     "step_counts": [100, 200]
+//! }
+//! ```
+//! Keyframes are either the path to a config file, relative to the animation file, 
+//! whose transforms are used, or the transforms themselves. Either can be given a `camera` 
+//! to pan, zoom and rotate the view, written `{"path": "start.json", "camera": {...}}` for paths. 
+//! 
+//! Each segment between keyframes can have its own `easings` curve, keyframes can be held for 
+//! a number of `holds` frames, and `interpolation` can run a Catmull-Rom spline through all 
+//! keyframes instead of morphing straight between each pair, see the `easing` module. 
+//! The `mode` can loop back to the first keyframe or play the keyframes forwards and then backwards, 
+//! see `AnimationMode`. 
+//! 
+//! Every frame is normally exposed on its own when tone mapping, which can make the animation 
+//! flicker as the attractor changes. The `exposure` setting can instead share one exposure across 
+//! the whole animation or smooth it over neighboring frames, see `Exposure`. 
+//! 
+//! If `image_settings.path` ends in `.gif`, `.png`, `.apng` or `.webp` the animation is encoded 
+//! into that file, timed by the optional `playback` settings. Otherwise it names a directory 
+//! that numbered frames are written into. 
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use rand::{rngs::StdRng, SeedableRng};
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use crate::{ifs::IFS, image::{Background, HdrStage, Image}};
+use crate::camera::Camera;
+use crate::checkpoint::chunk_seed;
+use crate::easing::{catmull_rom_coefficients, Easing, Interpolation};
+use crate::config::{Config, EvaluationSettings, ImageSettings};
+use crate::encode::{AnimationFormat, AnimationWriter};
+use crate::transform::Transform;
+use crate::transition::Transitions;
 
 /// Representation of animation.
+#[derive(Default)]
 pub struct AnimationSequence {
     pub ifs_vec: Vec<IFS>,
-    pub step_counts: Vec<usize>
+    pub step_counts: Vec<usize>,
+    /// easing curve of each segment between keyframes, missing ones are linear
+    pub easings: Vec<Easing>,
+    /// number of frames each keyframe is held for before moving on, missing ones are zero
+    pub holds: Vec<usize>,
+    /// how the path runs through the keyframes
+    pub interpolation: Interpolation,
+    /// whether the animation plays once, loops back to the first keyframe, or plays forwards then backwards
+    pub mode: AnimationMode,
+    /// view of each keyframe, keyframes without one use the default view
+    pub cameras: Vec<Camera>,
+    /// optional blur of the motion during each frame
+    pub motion_blur: Option<MotionBlur>,
+    /// number of frames rendered at the same time, each on its own thread. Zero or one renders them one after another.
+    pub workers: usize,
+    /// what shows through where few or no points landed in each frame, only colors can be encoded
+    pub background: Background,
+    /// seed the frames are drawn from, frame `i` with `chunk_seed(seed, i)`, so they can be rendered again exactly. 
+    /// Frames are drawn at random if missing. 
+    pub seed: Option<u64>
+}
+
+/// Motion blur, spreading the samples of each frame over the time the shutter is open
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
+pub struct MotionBlur {
+    /// number of moments between opening and closing the shutter that the IFS is evaluated at
+    #[schemars(range(min = 1))]
+    pub sub_steps: usize,
+    /// how long the shutter is open in degrees, where 360 is the whole time until the next frame
+    #[serde(default = "default_shutter_angle")]
+    #[schemars(range(min = 0.0, max = 360.0))]
+    pub shutter_angle: f32
+}
+
+fn default_shutter_angle() -> f32 {
+    180.0
+}
+
+/// How an animation runs through its keyframes
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Default)]
+pub enum AnimationMode {
+    /// from the first keyframe to the last
+    #[default]
+    Once,
+    /// from the first keyframe to the last and back to the first, with one more step count for the closing segment.
+    /// Without it, the closing segment takes as many steps as the first one.
+    #[serde(alias = "loop")]
+    Loop,
+    /// from the first keyframe to the last, then back through them in reverse
+    #[serde(alias = "ping_pong")]
+    PingPong
+}
+
+/// A run of frames morphing between keyframe `pair_index` and the one after it
+#[derive(Copy, Clone)]
+struct Segment {
+    pair_index: usize,
+    /// played from the later keyframe back to the earlier one
+    reversed: bool,
+    steps: usize
 }
 
 impl AnimationSequence {
+    /// The segments of the animation in the order they play
+    fn segments(&self) -> Vec<Segment> {
+        let num_keyframes = self.ifs_vec.len();
+        let forward = |pair_index: usize, &steps: &usize| Segment { pair_index, reversed: false, steps };
+        match self.mode {
+            AnimationMode::Once => self.step_counts.iter().take(num_keyframes.saturating_sub(1))
+                .enumerate().map(|(i, steps)| forward(i, steps)).collect(),
+            AnimationMode::Loop => (0..num_keyframes)
+                .map(|i| forward(i, self.step_counts.get(i).unwrap_or(&self.step_counts[0])))
+                .collect(),
+            AnimationMode::PingPong => {
+                let mut segments: Vec<Segment> = self.step_counts.iter().take(num_keyframes.saturating_sub(1))
+                    .enumerate().map(|(i, steps)| forward(i, steps)).collect();
+                let back: Vec<Segment> = segments.iter().rev().map(|s| Segment { reversed: true, ..*s }).collect();
+                segments.extend(back);
+                segments
+            }
+        }
+    }
+
+    /// Index into `ifs_vec` of the keyframe `offset` away from keyframe `pair_index`. 
+    /// Looping animations wrap around, others stop at the first and last keyframes. 
+    fn keyframe_index(&self, pair_index: usize, offset: isize) -> usize {
+        let index = pair_index as isize + offset;
+        let num_keyframes = self.ifs_vec.len() as isize;
+        match self.mode {
+            AnimationMode::Loop => index.rem_euclid(num_keyframes) as usize,
+            _ => index.clamp(0, num_keyframes - 1) as usize
+        }
+    }
+
+    /// Bring a step past the end of a looping animation back around into it
+    fn wrap_step(&self, current_step: usize) -> usize {
+        let total: usize = self.segments().iter().map(|s| s.steps).sum();
+        match self.mode {
+            AnimationMode::Once => current_step,
+            _ if total == 0 => current_step,
+            // steps count from 1, so step 0 and step `total` are both the first keyframe
+            _ => (current_step + total - 1) % total + 1
+        }
+    }
+
     fn determine_current_pair_index(&self, current_step: usize) -> usize {
+        let segments = self.segments();
         let mut pair_index = 0;
         let mut accumulator = 0;
         while accumulator < current_step {
-            accumulator += self.step_counts.get(pair_index).expect("current_step exceeds total step count.");
+            accumulator += segments.get(pair_index).expect("current_step exceeds total step count.").steps;
             pair_index += 1;
         }
-        pair_index - 1
+        pair_index.saturating_sub(1)
     }
 
     fn determine_current_pct(&self, current_step: usize, pair_index: usize) -> f32{
+        let segments = self.segments();
         let mut current_pair = 0;
         let mut accumulator = 0;
         while current_pair < pair_index {
-            accumulator += self.step_counts.get(current_pair).expect("current_step exceeds total step count.");
+            accumulator += segments.get(current_pair).expect("current_step exceeds total step count.").steps;
             current_pair += 1;
         }
         let this_pair_steps = current_step - accumulator;
-        this_pair_steps as f32 / *self.step_counts.get(pair_index).unwrap() as f32
+        this_pair_steps as f32 / segments.get(pair_index).unwrap().steps as f32
     }
 
     /// Animate to return only one image at a given `current_step`.
+    /// 
+    /// With `AnimationMode::Loop` or `AnimationMode::PingPong`, steps past the end wrap around to the start. 
     /// ```rust
     /// use barnsley::{transform::AffineTransform, ifs::IFS, animation::AnimationSequence};
     /// 
@@ -49,24 +196,278 @@ impl AnimationSequence {
     /// let mut end = IFS::new();
     /// end.add_transform(AffineTransform::random().into());
     /// 
-    /// let animation = AnimationSequence{ifs_vec: vec![start, middle, end], step_counts: vec![10, 20]};
+    /// let animation = AnimationSequence{ifs_vec: vec![start, middle, end], step_counts: vec![10, 20], ..Default::default()};
     /// let result = animation.animate_single_step(100, 100, 100, 100, 11);
     /// ```
     pub fn animate_single_step(&self, width: usize, height: usize, 
         num_iterations: usize, num_points: usize, current_step: usize) -> Image {
+            let current_step = self.wrap_step(current_step);
             let pair_index = self.determine_current_pair_index(current_step);
 
-            let start = self.ifs_vec.get(pair_index).unwrap();
-            let end = self.ifs_vec.get(pair_index + 1).unwrap();
             let pct = self.determine_current_pct(current_step, pair_index); 
-            let this_ifs = start.morph(end, pct);
+            let this_ifs = self.interpolated_ifs(pair_index, pct);
+            let camera = self.interpolated_camera(pair_index, pct);
 
             let mut this_image = Image::new(width, height);
-            this_ifs.evaluate(&mut this_image, num_points, num_iterations);
+            this_ifs.evaluate_with_camera(&mut this_image, num_points, num_iterations, &camera);
             this_image
     }
 
+    /// Total number of frames in the animation, including held frames
+    pub fn num_frames(&self) -> usize {
+        let segments = self.segments();
+        let played: usize = segments.iter().map(|s| s.steps + self.hold(self.segment_start(s))).sum();
+        match self.mode {
+            AnimationMode::Once => played + self.hold(self.ifs_vec.len().saturating_sub(1)),
+            _ => played
+        }
+    }
+
+    /// Number of frames keyframe `keyframe` is held for
+    fn hold(&self, keyframe: usize) -> usize {
+        self.holds.get(keyframe).copied().unwrap_or(0)
+    }
+
+    /// The keyframe a segment starts from
+    fn segment_start(&self, segment: &Segment) -> usize {
+        self.keyframe_index(segment.pair_index, if segment.reversed { 1 } else { 0 })
+    }
+
+    /// Find which segment of the animation frame number `frame` falls in and how far along it it is. 
+    /// 
+    /// Frames are laid out as the hold of the first keyframe, the steps to the second, its hold, and so on. 
+    /// Animations that play once end with the hold of the last keyframe. 
+    fn frame_position(&self, frame: usize) -> (usize, f32) {
+        if frame >= self.num_frames() {
+            panic!("frame {} exceeds the {} frames in the animation", frame, self.num_frames())
+        }
+        self.time_position(frame as f32)
+    }
+
+    /// Like `frame_position` for a time between frames, measured in frames. 
+    /// Looping animations wrap around, others stay on the last keyframe past the end. 
+    fn time_position(&self, time: f32) -> (usize, f32) {
+        let segments = self.segments();
+        let time = match self.mode {
+            AnimationMode::Once => time,
+            _ => time.rem_euclid(self.num_frames().max(1) as f32)
+        };
+        let mut first_frame_of_pair = 0;
+        for (segment_index, segment) in segments.iter().enumerate() {
+            first_frame_of_pair += self.hold(self.segment_start(segment));
+            if time < first_frame_of_pair as f32 {
+                return (segment_index, 0.0)
+            }
+            if time < (first_frame_of_pair + segment.steps) as f32 {
+                return (segment_index, (time - first_frame_of_pair as f32) / segment.steps as f32)
+            }
+            first_frame_of_pair += segment.steps;
+        }
+        (segments.len() - 1, 1.0)
+    }
+
+    /// The segment `segment_index` and how far along its pair of keyframes `pct` of the way through it is, after easing
+    fn eased_position(&self, segment_index: usize, pct: f32) -> (Segment, f32) {
+        let segment = self.segments()[segment_index];
+        let easing = self.easings.get(segment.pair_index).copied().unwrap_or_default();
+        // a reversed segment plays its pair backwards in time, easing included
+        let pct = if segment.reversed { 1.0 - easing.apply(1.0 - pct) } else { easing.apply(pct) };
+        (segment, pct)
+    }
+
+    /// The camera `pct` of the way through segment `segment_index`, after easing
+    fn interpolated_camera(&self, segment_index: usize, pct: f32) -> Camera {
+        let (segment, pct) = self.eased_position(segment_index, pct);
+        let key = |offset: isize| self.cameras.get(self.keyframe_index(segment.pair_index, offset)).copied().unwrap_or_default();
+        match self.interpolation {
+            Interpolation::Linear => key(0).morph(&key(1), pct),
+            Interpolation::CatmullRom => Camera::blend(&[key(-1), key(0), key(1), key(2)], &catmull_rom_coefficients(pct))
+        }
+    }
+
+    /// The IFS `pct` of the way through segment `segment_index`, after easing
+    fn interpolated_ifs(&self, segment_index: usize, pct: f32) -> IFS {
+        let (segment, pct) = self.eased_position(segment_index, pct);
+        let key = |offset: isize| &self.ifs_vec[self.keyframe_index(segment.pair_index, offset)];
+        match self.interpolation {
+            Interpolation::Linear => key(0).morph(key(1), pct),
+            Interpolation::CatmullRom => {
+                // at the ends of animations that do not loop, the first and last keyframes stand in for the missing neighbors
+                let keys = [key(-1), key(0), key(1), key(2)];
+                // keyframes with different transforms can only be morphed pairwise
+                if keys.iter().all(|k| keys[1].check_transforms_match(k)) {
+                    IFS::blend(&keys, &catmull_rom_coefficients(pct))
+                } else {
+                    keys[1].morph(keys[2], pct)
+                }
+            }
+        }
+    }
+
+    /// The interpolated IFS shown in frame number `frame`, counting from zero
+    pub fn frame_ifs(&self, frame: usize) -> IFS {
+        let (pair_index, pct) = self.frame_position(frame);
+        self.interpolated_ifs(pair_index, pct)
+    }
+
+    /// The interpolated camera of frame number `frame`, counting from zero
+    pub fn frame_camera(&self, frame: usize) -> Camera {
+        let (pair_index, pct) = self.frame_position(frame);
+        self.interpolated_camera(pair_index, pct)
+    }
+
+    /// Render frame number `frame`, counting from zero. 
+    /// 
+    /// With `motion_blur` the points are split across sub-steps between the frame and the next, 
+    /// each evaluated with the IFS and camera at that moment, and all land in the same image. 
+    pub fn render_frame(&self, frame: usize, width: usize, height: usize, 
+        num_iterations: usize, num_points: usize) -> Image {
+        self.render_seeded_frame(frame, width, height, num_iterations, num_points, frame_seed(self.seed, frame))
+    }
+
+    fn render_seeded_frame(&self, frame: usize, width: usize, height: usize, 
+        num_iterations: usize, num_points: usize, seed: u64) -> Image {
+        let mut this_image = Image::new(width, height);
+        let rng = &mut StdRng::seed_from_u64(seed);
+        match self.motion_blur {
+            None => self.frame_ifs(frame).evaluate_with_rng(&mut this_image, num_points, num_iterations, &self.frame_camera(frame), rng),
+            Some(blur) => {
+                if frame >= self.num_frames() {
+                    panic!("frame {} exceeds the {} frames in the animation", frame, self.num_frames())
+                }
+                let sub_steps = blur.sub_steps.max(1);
+                for sub_step in 0..sub_steps {
+                    let time = frame as f32 + blur.shutter_angle / 360.0 * sub_step as f32 / sub_steps as f32;
+                    let (segment_index, pct) = self.time_position(time);
+                    let sub_step_points = num_points / sub_steps + usize::from(sub_step < num_points % sub_steps);
+                    self.interpolated_ifs(segment_index, pct).evaluate_with_rng(&mut this_image, sub_step_points, num_iterations,
+                                                                                &self.interpolated_camera(segment_index, pct), rng);
+                }
+            }
+        }
+        this_image
+    }
+
+    /// Render the frames numbered `indices` at the same time, each on its own thread, returned in the same order
+    pub fn render_frames(&self, indices: &[usize], width: usize, height: usize, 
+        num_iterations: usize, num_points: usize) -> Vec<Image> {
+        self.render_seeded_frames(indices, width, height, num_iterations, num_points, self.seed)
+    }
+
+    fn render_seeded_frames(&self, indices: &[usize], width: usize, height: usize, 
+        num_iterations: usize, num_points: usize, seed: Option<u64>) -> Vec<Image> {
+        let render = |frame: usize| self.render_seeded_frame(frame, width, height, num_iterations, num_points, frame_seed(seed, frame));
+        if let [frame] = indices {
+            return vec![render(*frame)]
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = indices.iter()
+                .map(|&frame| scope.spawn(move || render(frame)))
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        })
+    }
+
+    /// Iterate over the frames of the animation. 
+    /// 
+    /// Frames are only rendered when the iterator reaches them, `workers` at a time, so at most that many 
+    /// frames are held in memory. They always come out in order. Skipping frames with `skip` or `nth` 
+    /// does not render them. 
+    /// ```rust
+    /// use barnsley::{transform::AffineTransform, ifs::IFS, animation::AnimationSequence};
+    /// 
+    /// let mut start = IFS::new();
+    /// start.add_transform(AffineTransform::random().into());
+    /// 
+    /// let mut end = IFS::new();
+    /// end.add_transform(AffineTransform::random().into());
+    /// 
+    /// let animation = AnimationSequence{ifs_vec: vec![start, end], step_counts: vec![5], ..Default::default()};
+    /// for (index, frame) in animation.frames(100, 100, 100, 100).enumerate().skip(2) {
+    ///     frame.save(&format!("frame_{}.png", index), 1);
+    /// }
+    /// # for index in 2..5 { std::fs::remove_file(format!("frame_{}.png", index)).unwrap(); }
+    /// ```
+    pub fn frames(&self, width: usize, height: usize, num_iterations: usize, num_points: usize) -> Frames<'_> {
+        Frames {
+            sequence: self,
+            next_frame: 0,
+            rendered: VecDeque::new(),
+            seed: self.seed,
+            width,
+            height,
+            num_iterations,
+            num_points
+        }
+    }
+
+    /// Render the animation straight into an animated GIF, PNG or WebP at `path`, chosen by its extension. 
+    /// Frames are encoded as they are rendered, over the `background` color. 
+    /// ```rust
+    /// use barnsley::{transform::AffineTransform, ifs::IFS, animation::{AnimationSequence, Exposure, PlaybackSettings}};
+    /// 
+    /// let mut start = IFS::new();
+    /// start.add_transform(AffineTransform::random().into());
+    /// 
+    /// let mut end = IFS::new();
+    /// end.add_transform(AffineTransform::random().into());
+    /// 
+    /// let animation = AnimationSequence{ifs_vec: vec![start, end], step_counts: vec![3], ..Default::default()};
+    /// animation.encode("movie.gif", 100, 100, 100, 100, &PlaybackSettings::default(), Exposure::Global);
+    /// # std::fs::remove_file("movie.gif").unwrap();
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(&self, path: &str, width: usize, height: usize, num_iterations: usize, num_points: usize, 
+        playback: &PlaybackSettings, exposure: Exposure) {
+        let path = Path::new(path);
+        let format = AnimationFormat::from_path(path)
+            .unwrap_or_else(|| panic!("{} is not a .gif, .png, .apng or .webp file", path.display()));
+        if self.background == Background::Transparent {
+            panic!("{} can not be transparent, only frames written to a directory can", path.display());
+        }
+
+        let mut writer = AnimationWriter::new(path, format, width as u32, height as u32, 
+                                              self.num_frames() as u32, playback.loop_count);
+        let frames = self.exposed_frames(width, height, num_iterations, num_points, exposure);
+        for (index, (frame, log_mean_luminance)) in frames.enumerate() {
+            let pixels = frame.to_display_image(frame.samples_per_pixel(), log_mean_luminance, false, self.background);
+            writer.write_frame(&pixels.into_rgb8(), playback.delay_ms(index));
+        }
+        writer.finish();
+    }
+
+    /// Iterate over the frames of the animation along with the log-mean luminance to expose each with, 
+    /// see `Image::to_rgb_image_with_exposure`. Luminances are measured for the samples each frame drew, 
+    /// `Image::samples_per_pixel`, which the frame should be tone mapped with too. 
+    /// 
+    /// With `Exposure::Global` every frame is rendered twice, once up front to measure it and again when the 
+    /// iterator reaches it. Both passes draw the same points, with a random `seed` picked for them if there is none. 
+    /// With `Exposure::Smoothed` up to half a window of frames is held in memory. 
+    pub fn exposed_frames(&self, width: usize, height: usize, num_iterations: usize, num_points: usize, 
+        exposure: Exposure) -> ExposedFrames<'_> {
+        let mut frames = self.frames(width, height, num_iterations, num_points);
+        let global_log_luminance = match exposure {
+            Exposure::Global => {
+                frames.seed = Some(self.seed.unwrap_or_else(rand::random));
+                let mut measured = Frames { seed: frames.seed, ..self.frames(width, height, num_iterations, num_points) };
+                let count = measured.len();
+                let sum_of_logs: f32 = measured.by_ref().map(|frame| frame.log_mean_luminance(frame.samples_per_pixel()).log10()).sum();
+                sum_of_logs / count.max(1) as f32
+            },
+            _ => 0.0
+        };
+        ExposedFrames {
+            frames,
+            exposure,
+            global_log_luminance,
+            lookahead: VecDeque::new(),
+            history: VecDeque::new()
+        }
+    }
+
     /// Animate all steps in an IFS to get a `Vec<Image>`.
+    /// 
+    /// This keeps every frame in memory, use `frames` for long or large animations. 
     /// ```rust
     /// use barnsley::{transform::AffineTransform, ifs::IFS, animation::AnimationSequence};
     /// 
@@ -79,34 +480,409 @@ impl AnimationSequence {
     /// let mut end = IFS::new();
     /// end.add_transform(AffineTransform::random().into());
     /// 
-    /// let animation = AnimationSequence{ifs_vec: vec![start, middle, end], step_counts: vec![2, 3]};
+    /// let animation = AnimationSequence{ifs_vec: vec![start, middle, end], step_counts: vec![2, 3], ..Default::default()};
     /// let movie = animation.animate(100, 100, 100, 100);
     /// ```
     pub fn animate(&self, width: usize, height: usize, num_iterations: usize, num_points: usize) -> Vec<Image> {
-        let mut images = vec![];
+        self.frames(width, height, num_iterations, num_points).collect()
+    }
+}
 
-        for pair_index in 0..self.ifs_vec.len()-1 {
-            let num_steps_for_pair = *self.step_counts.get(pair_index).unwrap();
+/// Seed of frame number `frame` in an animation seeded with `seed`, a random one if there is none
+fn frame_seed(seed: Option<u64>, frame: usize) -> u64 {
+    seed.map_or_else(rand::random, |seed| chunk_seed(seed, frame))
+}
 
-            let start = self.ifs_vec.get(pair_index).unwrap();
-            let end = self.ifs_vec.get(pair_index + 1).unwrap();
-            for step in 0..num_steps_for_pair{
+/// Iterator over the rendered frames of an `AnimationSequence`, created by `AnimationSequence::frames`
+pub struct Frames<'a> {
+    sequence: &'a AnimationSequence,
+    /// the next frame to render, frames before it are either yielded or in `rendered`
+    next_frame: usize,
+    /// frames rendered together but not yet yielded, in order
+    rendered: VecDeque<Image>,
+    /// seed the frames are drawn from, see `AnimationSequence::seed`
+    seed: Option<u64>,
+    width: usize,
+    height: usize,
+    num_iterations: usize,
+    num_points: usize
+}
 
-                let this_ifs = start.morph(end, step as f32 / num_steps_for_pair as f32);
+impl Iterator for Frames<'_> {
+    type Item = Image;
 
-                let mut this_image = Image::new(width, height);
-                this_ifs.evaluate(&mut this_image, num_points, num_iterations);
+    fn next(&mut self) -> Option<Image> {
+        if self.rendered.is_empty() {
+            let batch_end = self.sequence.num_frames().min(self.next_frame.saturating_add(self.sequence.workers.max(1)));
+            if self.next_frame >= batch_end {
+                return None
+            }
+            let indices: Vec<usize> = (self.next_frame..batch_end).collect();
+            self.rendered.extend(self.sequence.render_seeded_frames(&indices, self.width, self.height, 
+                                                                    self.num_iterations, self.num_points, self.seed));
+            self.next_frame = batch_end;
+        }
+        self.rendered.pop_front()
+    }
 
-                images.insert(images.len(), this_image);
+    fn nth(&mut self, n: usize) -> Option<Image> {
+        let already_rendered = n.min(self.rendered.len());
+        self.rendered.drain(..already_rendered);
+        self.next_frame = self.next_frame.saturating_add(n - already_rendered);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.sequence.num_frames().saturating_sub(self.next_frame) + self.rendered.len();
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Frames<'_> {}
+
+/// How the frames of an animation are exposed when tone mapping
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Default)]
+pub enum Exposure {
+    /// every frame is exposed on its own, brightness can flicker as the attractor changes
+    #[default]
+    PerFrame,
+    /// one exposure for the whole animation, every frame is rendered twice, drawing the same points, to measure it first
+    Global,
+    /// each frame's exposure is averaged over a sliding window of `window` frames centered on it
+    Smoothed {
+        #[schemars(range(min = 1))]
+        window: usize
+    }
+}
+
+/// Iterator over rendered frames and the log-mean luminance to expose them with, created by 
+/// `AnimationSequence::exposed_frames`
+pub struct ExposedFrames<'a> {
+    frames: Frames<'a>,
+    exposure: Exposure,
+    /// mean of the base 10 log of every frame's log-mean luminance, for `Exposure::Global`
+    global_log_luminance: f32,
+    /// upcoming frames and the log of their luminance, for `Exposure::Smoothed`
+    lookahead: VecDeque<(Image, f32)>,
+    /// log of the luminance of recent frames, for `Exposure::Smoothed`
+    history: VecDeque<f32>
+}
+
+impl Iterator for ExposedFrames<'_> {
+    type Item = (Image, f32);
+
+    fn next(&mut self) -> Option<(Image, f32)> {
+        match self.exposure {
+            Exposure::PerFrame => self.frames.next().map(|frame| {
+                let log_mean_luminance = frame.log_mean_luminance(frame.samples_per_pixel());
+                (frame, log_mean_luminance)
+            }),
+            Exposure::Global => self.frames.next().map(|frame| (frame, 10.0f32.powf(self.global_log_luminance))),
+            Exposure::Smoothed { window } => {
+                let before = (window.max(1) - 1) / 2;
+                let after = window.max(1) - 1 - before;
+                while self.lookahead.len() <= after {
+                    match self.frames.next() {
+                        Some(frame) => {
+                            let log = frame.log_mean_luminance(frame.samples_per_pixel()).log10();
+                            self.lookahead.push_back((frame, log));
+                        },
+                        None => break
+                    }
+                }
+                let (frame, log) = self.lookahead.pop_front()?;
+
+                // luminances are averaged in log space, matching how each one is a log mean
+                let logs: Vec<f32> = self.history.iter().copied()
+                    .chain(std::iter::once(log))
+                    .chain(self.lookahead.iter().take(after).map(|(_, l)| *l))
+                    .collect();
+                let smoothed = logs.iter().sum::<f32>() / logs.len() as f32;
+
+                self.history.push_back(log);
+                if self.history.len() > before {
+                    self.history.pop_front();
+                }
+                Some((frame, 10.0f32.powf(smoothed)))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.frames.len() + self.lookahead.len();
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for ExposedFrames<'_> {}
+
+/// Timing of an animation encoded to an animated image format
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PlaybackSettings {
+    /// frames per second, used for every frame without an entry in `frame_delays`
+    #[serde(default = "default_frame_rate")]
+    #[schemars(range(min = 0.001))]
+    pub frame_rate: f32,
+    /// how many times the animation plays, zero loops forever
+    #[serde(default)]
+    pub loop_count: u16,
+    /// how long each frame is shown in milliseconds, starting from the first frame
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frame_delays: Vec<u32>
+}
+
+fn default_frame_rate() -> f32 {
+    24.0
+}
+
+impl PlaybackSettings {
+    /// How long frame number `frame` is shown in milliseconds
+    pub fn delay_ms(&self, frame: usize) -> u32 {
+        match self.frame_delays.get(frame) {
+            Some(delay) => *delay,
+            None => (1000.0 / self.frame_rate).round() as u32
+        }
+    }
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        PlaybackSettings { frame_rate: default_frame_rate(), loop_count: 0, frame_delays: vec![] }
+    }
+}
+
+/// A keyframe of an animation file
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum Keyframe {
+    /// path to a config file whose transforms are used, relative to the animation file
+    Path(String),
+    /// path to a config file whose transforms are used, seen through a camera
+    Viewed {
+        /// path to the config file, relative to the animation file
+        path: String,
+        /// view of the keyframe, the default view if missing
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        camera: Option<Camera>
+    },
+    /// transforms given directly in the animation file
+    Inline {
+        /// fully parameterized transforms of the IFS
+        transforms: Vec<Transform>,
+        /// optional rules that make the choice of the next transform depend on the previous one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transitions: Option<Transitions>,
+        /// optional names of the transforms, used to pair up transforms with other keyframes
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        labels: Vec<Option<String>>,
+        /// view of the keyframe, the default view if missing
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        camera: Option<Camera>
+    }
+}
+
+impl Keyframe {
+    /// Build the IFS for the keyframe, resolving paths relative to `base_directory`
+    pub fn ifs(&self, base_directory: &Path) -> IFS {
+        match self {
+            Keyframe::Path(path) | Keyframe::Viewed { path, .. } => {
+                let path = base_directory.join(path);
+                let mut data = String::new();
+                File::open(&path).unwrap_or_else(|e| panic!("could not open keyframe {}: {}", path.display(), e))
+                    .read_to_string(&mut data).unwrap();
+                let config: Config = serde_json::from_str(&data)
+                    .unwrap_or_else(|e| panic!("could not parse keyframe {}: {}", path.display(), e));
+                config.ifs()
+            },
+            Keyframe::Inline { transforms, transitions, labels, .. } => {
+                let mut ifs = IFS::from_transforms(transforms.clone(), transitions.clone());
+                ifs.set_labels(labels.clone());
+                ifs
+            }
+        }
+    }
+
+    /// The view of the keyframe
+    pub fn camera(&self) -> Camera {
+        match self {
+            Keyframe::Path(_) => Camera::default(),
+            Keyframe::Viewed { camera, .. } | Keyframe::Inline { camera, .. } => camera.unwrap_or_default()
+        }
+    }
+}
+
+/// Animation files define an animation: the keyframes, how many steps go between them, 
+/// and the image and evaluation settings shared by every frame. 
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AnimationConfig {
+    /// file format version, see `SCHEMA_VERSION`
+    #[serde(default)]
+    pub version: u32,
+    /// size of every frame, `path` is the animated image file or the directory frames are written to
+    pub image_settings: ImageSettings,
+    /// how much work goes into each frame
+    pub evaluation_settings: EvaluationSettings,
+    /// the IFSes to interpolate between
+    #[schemars(length(min = 2))]
+    pub keyframes: Vec<Keyframe>,
+    /// number of frames between each pair of keyframes, one fewer than the keyframes, 
+    /// or as many as the keyframes when looping
+    pub step_counts: Vec<usize>,
+    /// easing curve of each segment between keyframes, linear when missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub easings: Vec<Easing>,
+    /// number of frames to hold each keyframe for, zero when missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holds: Vec<usize>,
+    /// whether to morph straight between keyframes or follow a spline through all of them
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// play the keyframes once, loop back to the first, or play them forwards then backwards
+    #[serde(default)]
+    pub mode: AnimationMode,
+    /// optional motion blur within each frame
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion_blur: Option<MotionBlur>,
+    /// timing when encoding to an animated image format
+    #[serde(default)]
+    pub playback: PlaybackSettings,
+    /// how frames are exposed when tone mapping, each on its own by default
+    #[serde(default)]
+    pub exposure: Exposure
+}
+
+impl AnimationConfig {
+    /// Build the animation sequence, resolving keyframe paths relative to `base_directory`.
+    ///
+    /// Panics if there are fewer than two keyframes or the step counts, easings and holds do not fit them.
+    pub fn sequence(&self, base_directory: &Path) -> AnimationSequence {
+        if self.keyframes.len() < 2 {
+            panic!("an animation needs at least two keyframes, not {}", self.keyframes.len());
+        }
+        // a loop may give a step count for its closing segment back to the first keyframe
+        let num_segments = match self.mode {
+            AnimationMode::Loop => self.keyframes.len(),
+            _ => self.keyframes.len().saturating_sub(1)
+        };
+        let loop_without_closing_count = self.mode == AnimationMode::Loop && self.step_counts.len() + 1 == self.keyframes.len();
+        if self.step_counts.len() != num_segments && !loop_without_closing_count {
+            panic!("there are {} keyframes so there must be {} step counts, not {}",
+                   self.keyframes.len(), num_segments, self.step_counts.len());
+        }
+        if let Some(index) = self.step_counts.iter().position(|&steps| steps == 0) {
+            panic!("step count {} is zero, every segment between keyframes needs at least one frame", index);
+        }
+        if self.easings.len() > num_segments {
+            panic!("there are {} easings but only {} segments between keyframes", self.easings.len(), num_segments);
+        }
+        if self.holds.len() > self.keyframes.len() {
+            panic!("there are {} holds but only {} keyframes", self.holds.len(), self.keyframes.len());
+        }
+        AnimationSequence {
+            ifs_vec: self.keyframes.iter().map(|k| k.ifs(base_directory)).collect(),
+            step_counts: self.step_counts.clone(),
+            easings: self.easings.clone(),
+            holds: self.holds.clone(),
+            interpolation: self.interpolation,
+            mode: self.mode,
+            cameras: self.keyframes.iter().map(|k| k.camera()).collect(),
+            motion_blur: self.motion_blur,
+            workers: 1,
+            background: self.image_settings.background(),
+            seed: self.evaluation_settings.seed
+        }
+    }
+
+    /// The settings given that animations ignore: every frame is an 8-bit image rendered whole and at once, 
+    /// and animated image files can not be `transparent`. 
+    pub fn unsupported_settings(&self) -> Vec<&'static str> {
+        let image = &self.image_settings;
+        let evaluation = &self.evaluation_settings;
+        let encoded = AnimationFormat::from_path(Path::new(&image.path)).is_some();
+        [
+            ("image_settings.output_format", image.output_format.is_some()),
+            ("image_settings.hdr_stage", image.hdr_stage != HdrStage::default()),
+            ("image_settings.preview", image.preview.is_some()),
+            ("image_settings.tile_rows", image.tile_rows.is_some()),
+            ("image_settings.transparent in an animated image file", image.transparent && encoded),
+            ("evaluation_settings.checkpoint_every", evaluation.checkpoint_every.is_some()),
+            ("evaluation_settings.target", evaluation.target.is_some())
+        ].into_iter().filter(|&(_, given)| given).map(|(name, _)| name).collect()
+    }
+
+    /// Path of frame number `index` in the output directory
+    pub fn frame_path(&self, index: usize) -> PathBuf {
+        Path::new(&self.image_settings.path).join(format!("frame_{:05}.png", index))
+    }
+
+    /// Save frame number `index` into the output directory, exposed for `log_mean_luminance`, 
+    /// over the background of the image settings. 
+    /// 
+    /// The frame is written under a temporary name and renamed, so a frame cut short by an interrupted 
+    /// run never counts as done when resuming. Panics if the frame can not be written. 
+    pub fn save_frame(&self, index: usize, frame: &Image, log_mean_luminance: f32) {
+        let path = self.frame_path(index);
+        let unfinished = path.with_extension("png.partial");
+        frame.to_display_image(frame.samples_per_pixel(), log_mean_luminance, false, self.image_settings.background())
+            .save_with_format(&unfinished, image::ImageFormat::Png)
+            .unwrap_or_else(|e| panic!("could not save {}: {}", unfinished.display(), e));
+        fs::rename(&unfinished, &path)
+            .unwrap_or_else(|e| panic!("could not move {} to {}: {}", unfinished.display(), path.display(), e));
+    }
+
+    /// Render the animation, resolving keyframe paths relative to `base_directory`. 
+    /// 
+    /// When the output path is an animated image file the frames are encoded into it. Otherwise every frame 
+    /// is written into the output directory as soon as it is rendered. With `resume`, frames that already exist 
+    /// in the output directory are skipped, so an interrupted animation can pick up where it stopped. 
+    /// `workers` frames are rendered at the same time. 
+    /// 
+    /// Panics if the settings ask for something only single images support, see `unsupported_settings`. 
+    pub fn run(&self, base_directory: &Path, resume: bool, workers: usize) {
+        let unsupported = self.unsupported_settings();
+        if !unsupported.is_empty() {
+            panic!("animations do not support {}", unsupported.join(", "));
+        }
+        let width = self.image_settings.width as usize;
+        let height = self.image_settings.height as usize;
+        let num_iterations = self.evaluation_settings.num_iterations as usize;
+        let num_points = self.evaluation_settings.num_points as usize;
+        let mut sequence = self.sequence(base_directory);
+        sequence.workers = workers;
+
+        if AnimationFormat::from_path(Path::new(&self.image_settings.path)).is_some() {
+            sequence.encode(&self.image_settings.path, width, height, 
+                            num_iterations, num_points, &self.playback, self.exposure);
+            return
+        }
+
+        fs::create_dir_all(&self.image_settings.path).unwrap();
+        if self.exposure == Exposure::PerFrame {
+            let missing: Vec<usize> = (0..sequence.num_frames())
+                .filter(|&index| !(resume && self.frame_path(index).exists()))
+                .collect();
+            for batch in missing.chunks(workers.max(1)) {
+                let frames = sequence.render_frames(batch, width, height, num_iterations, num_points);
+                for (&index, frame) in batch.iter().zip(frames) {
+                    self.save_frame(index, &frame, frame.log_mean_luminance(frame.samples_per_pixel()));
+                }
+            }
+        } else {
+            // existing frames still have to be rendered since their neighbors' exposure depends on them
+            let frames = sequence.exposed_frames(width, height, num_iterations, num_points, self.exposure);
+            for (index, (frame, log_mean_luminance)) in frames.enumerate() {
+                if resume && self.frame_path(index).exists() {
+                    continue
+                }
+                self.save_frame(index, &frame, log_mean_luminance);
             }
         }
-        images
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ifs::IFS, animation::AnimationSequence};
+    use crate::{ifs::IFS, image::Image, animation::{AnimationMode, AnimationSequence, Exposure, Keyframe, MotionBlur}, camera::Camera, easing::{Easing, Interpolation}};
 
     #[test]
     fn test_determine_current_pair_index() {
@@ -116,7 +892,7 @@ mod tests {
         let ifs4: IFS = IFS::new();
 
         let seq = AnimationSequence{ifs_vec: vec![ifs1, ifs2, ifs3, ifs4], 
-            step_counts: vec![100, 200, 300]};
+            step_counts: vec![100, 200, 300], ..Default::default()};
 
         assert_eq!(seq.determine_current_pair_index(30), 0);
         assert_eq!(seq.determine_current_pair_index(100), 0);
@@ -125,6 +901,39 @@ mod tests {
         assert_eq!(seq.determine_current_pair_index(305), 2);
     }
 
+    #[test]
+    fn test_determine_current_pair_index_loop() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::new(), IFS::new(), IFS::new()], 
+            step_counts: vec![100, 200, 50], mode: AnimationMode::Loop, ..Default::default()};
+
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(301)), 2);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(350)), 2);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(351)), 0);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(0)), 2);
+        assert!((seq.determine_current_pct(seq.wrap_step(375), 0) - 25.0/100.0).abs() < 0.01);
+        assert!((seq.determine_current_pct(seq.wrap_step(325), 2) - 25.0/50.0).abs() < 0.01);
+
+        // without a step count for the closing segment it takes as long as the first
+        let seq = AnimationSequence{ifs_vec: vec![IFS::new(), IFS::new(), IFS::new()], 
+            step_counts: vec![100, 200], mode: AnimationMode::Loop, ..Default::default()};
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(399)), 2);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(401)), 0);
+    }
+
+    #[test]
+    fn test_determine_current_pair_index_ping_pong() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::new(), IFS::new(), IFS::new()], 
+            step_counts: vec![100, 200], mode: AnimationMode::PingPong, ..Default::default()};
+
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(250)), 1);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(350)), 2);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(550)), 3);
+        assert_eq!(seq.determine_current_pair_index(seq.wrap_step(750)), 1);
+        assert!((seq.determine_current_pct(seq.wrap_step(350), 2) - 50.0/200.0).abs() < 0.01);
+        assert_eq!(seq.segments()[2].pair_index, 1);
+        assert!(seq.segments()[2].reversed);
+    }
+
     #[test]
     fn test_determine_current_pct() {
         let ifs1: IFS = IFS::new();
@@ -133,10 +942,191 @@ mod tests {
         let ifs4: IFS = IFS::new();
 
         let seq = AnimationSequence{ifs_vec: vec![ifs1, ifs2, ifs3, ifs4], 
-            step_counts: vec![100, 200, 300]};
+            step_counts: vec![100, 200, 300], ..Default::default()};
 
         assert!((seq.determine_current_pct(30, 0) - 0.3).abs() < 0.01);
         assert!((seq.determine_current_pct(101, 1) - 1.0/200.0).abs() < 0.01);
         assert!((seq.determine_current_pct(201, 1) - 101.0/200.0).abs() < 0.01);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_frame_position() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::sierpinski_triangle(), IFS::sierpinski_triangle(), IFS::sierpinski_triangle()], 
+            step_counts: vec![4, 10], ..Default::default()};
+
+        assert_eq!(seq.num_frames(), 14);
+        assert_eq!(seq.frame_position(0), (0, 0.0));
+        assert_eq!(seq.frame_position(2), (0, 0.5));
+        assert_eq!(seq.frame_position(4), (1, 0.0));
+        assert_eq!(seq.frame_position(13), (1, 0.9));
+        assert_eq!(seq.frames(10, 10, 1, 1).skip(12).count(), 2);
+    }
+
+    #[test]
+    fn test_exposed_frames() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::barnsley_fern(), IFS::koch_curve()], 
+            step_counts: vec![5], ..Default::default()};
+
+        for (frame, log_mean_luminance) in seq.exposed_frames(20, 20, 10, 40, Exposure::Smoothed { window: 1 }) {
+            assert_eq!(frame.log_mean_luminance(1), log_mean_luminance);
+        }
+
+        let global: Vec<(Image, f32)> = seq.exposed_frames(20, 20, 10, 40, Exposure::Global).collect();
+        assert_eq!(global.len(), 5);
+        assert!(global.iter().all(|(_, l)| *l == global[0].1));
+        // the frames handed out draw the same points as the ones that were measured
+        let mean_log = global.iter().map(|(frame, _)| frame.log_mean_luminance(1).log10()).sum::<f32>() / 5.0;
+        assert!((10.0f32.powf(mean_log) - global[0].1).abs() < 1e-4 * global[0].1);
+
+        let smoothed = seq.exposed_frames(20, 20, 10, 40, Exposure::Smoothed { window: 3 });
+        assert_eq!(smoothed.len(), 5);
+        assert_eq!(smoothed.count(), 5);
+    }
+
+    #[test]
+    fn test_holds_and_easing() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::sierpinski_triangle(), IFS::sierpinski_triangle(), IFS::sierpinski_triangle()], 
+            step_counts: vec![4, 10], holds: vec![2, 0, 3], easings: vec![Easing::EaseInCubic], 
+            interpolation: Interpolation::CatmullRom, ..Default::default()};
+
+        assert_eq!(seq.num_frames(), 19);
+        assert_eq!(seq.frame_position(1), (0, 0.0));
+        assert_eq!(seq.frame_position(4), (0, 0.5));
+        assert_eq!(seq.frame_position(6), (1, 0.0));
+        assert_eq!(seq.frame_position(16), (1, 1.0));
+        assert_eq!(seq.frame_position(18), (1, 1.0));
+        assert!(seq.frame_ifs(0).get_transform(0) == IFS::sierpinski_triangle().get_transform(0));
+        assert_eq!(seq.frames(10, 10, 1, 1).count(), 19);
+    }
+
+    #[test]
+    fn test_looping_frames() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::sierpinski_triangle(), IFS::sierpinski_triangle()], 
+            step_counts: vec![4], holds: vec![1, 2], mode: AnimationMode::PingPong, ..Default::default()};
+
+        // hold, there, hold, back
+        assert_eq!(seq.num_frames(), 11);
+        assert_eq!(seq.frame_position(5), (1, 0.0));
+        assert_eq!(seq.frame_position(7), (1, 0.0));
+        assert_eq!(seq.frame_position(8), (1, 0.25));
+        assert_eq!(seq.frames(10, 10, 1, 1).count(), 11);
+    }
+
+    #[test]
+    fn test_frame_camera() {
+        let zoomed = Camera { center: [0.5, 0.5], zoom: 4.0, rotation: 0.0 };
+        let seq = AnimationSequence{ifs_vec: vec![IFS::sierpinski_triangle(), IFS::sierpinski_triangle()], 
+            step_counts: vec![2], cameras: vec![Camera::default(), zoomed], ..Default::default()};
+
+        assert_eq!(seq.frame_camera(0), Camera::default());
+        assert!((seq.frame_camera(1).zoom - 2.0).abs() < 1e-5);
+        assert_eq!(seq.frame_camera(1).center, [0.25, 0.25]);
+
+        let keyframe: Keyframe = serde_json::from_str(r#"{"path": "start.json", "camera": {"zoom": 4.0}}"#).unwrap();
+        assert_eq!(keyframe.camera().zoom, 4.0);
+        assert_eq!(keyframe.camera().center, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_motion_blur() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::barnsley_fern(), IFS::koch_curve()], 
+            step_counts: vec![4], mode: AnimationMode::Loop, 
+            motion_blur: Some(MotionBlur { sub_steps: 3, shutter_angle: 180.0 }), ..Default::default()};
+
+        assert_eq!(seq.time_position(2.5), (0, 0.625));
+        assert_eq!(seq.time_position(6.5), (1, 0.625));
+        // the shutter of the last frame stays open into the first
+        assert_eq!(seq.time_position(8.5), (0, 0.125));
+        assert_eq!(seq.frames(10, 10, 5, 10).count(), 8);
+    }
+
+    #[test]
+    fn test_parallel_frames() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::barnsley_fern(), IFS::koch_curve()], 
+            step_counts: vec![7], workers: 3, ..Default::default()};
+
+        let mut frames = seq.frames(10, 10, 5, 10);
+        assert_eq!(frames.len(), 7);
+        frames.next();
+        assert_eq!(frames.len(), 6);
+        assert!(frames.nth(3).is_some());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames.count(), 2);
+    }
+
+    /// A small animation between two presets, writing its frames to `path`
+    fn test_animation(path: &std::path::Path) -> crate::animation::AnimationConfig {
+        use crate::presets::Preset;
+        let keyframe = |preset: Preset| serde_json::json!({"transforms": preset.transforms()});
+        serde_json::from_value(serde_json::json!({
+            "image_settings": {"width": 12, "height": 12, "path": path.to_str().unwrap()},
+            "evaluation_settings": {"num_iterations": 10, "num_points": 20, "seed": 5},
+            "keyframes": [keyframe(Preset::SierpinskiTriangle), keyframe(Preset::BarnsleyFern)],
+            "step_counts": [3]
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_frames_are_written_whole() {
+        let directory = crate::util::test_path("frames");
+        let animation = test_animation(&directory);
+        animation.run(std::path::Path::new(""), false, 2);
+
+        let mut names: Vec<String> = std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["frame_00000.png", "frame_00001.png", "frame_00002.png"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "at least two keyframes")]
+    fn test_single_keyframe_is_rejected() {
+        let mut animation = test_animation(std::path::Path::new("unused"));
+        animation.keyframes.truncate(1);
+        animation.step_counts.clear();
+        animation.holds = vec![3];
+        animation.sequence(std::path::Path::new(""));
+    }
+
+    #[test]
+    fn test_frames_use_background() {
+        let directory = crate::util::test_path("background");
+        let mut animation = test_animation(&directory);
+        animation.image_settings.background = Some(crate::util::Color{r: 0.0, g: 0.0, b: 1.0});
+        animation.run(std::path::Path::new(""), false, 1);
+
+        // the corners stay empty, so they show the background
+        let frame = image::open(animation.frame_path(0)).unwrap().to_rgb8();
+        assert_eq!(frame.get_pixel(0, 0).0, [0, 0, 255]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_settings() {
+        let mut animation = test_animation(std::path::Path::new("movie.gif"));
+        assert!(animation.unsupported_settings().is_empty());
+        animation.image_settings.tile_rows = Some(4);
+        animation.image_settings.transparent = true;
+        assert_eq!(animation.unsupported_settings(),
+                   vec!["image_settings.tile_rows", "image_settings.transparent in an animated image file"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "step count 0 is zero")]
+    fn test_zero_step_count_is_rejected() {
+        let mut animation = test_animation(std::path::Path::new("unused"));
+        animation.step_counts = vec![0];
+        animation.sequence(std::path::Path::new(""));
+    }
+
+    #[test]
+    fn test_seeded_frames_repeat() {
+        let seq = AnimationSequence{ifs_vec: vec![IFS::barnsley_fern(), IFS::koch_curve()], 
+            step_counts: vec![3], seed: Some(9), ..Default::default()};
+        let first = seq.render_frame(1, 20, 20, 10, 40);
+        assert_eq!(seq.render_frame(1, 20, 20, 10, 40).data(), first.data());
+        assert_ne!(seq.render_frame(2, 20, 20, 10, 40).data(), first.data());
+    }
+}
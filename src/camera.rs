@@ -0,0 +1,75 @@
+//! the view onto an IFS: where it is centered, how far it is zoomed in and how it is rotated
+//!
+//! The default camera shows the same view as `final_transform`. Animations can give each keyframe
+//! its own camera to pan, zoom and rotate over the attractor.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::transform::final_transform;
+use crate::util::blend_f32;
+
+/// View onto an IFS
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Camera {
+    /// point of the IFS shown at the center of the image
+    pub center: [f32; 2],
+    /// magnification, 2 shows half as much of the IFS at twice the size
+    #[schemars(range(min = 0.0))]
+    pub zoom: f32,
+    /// rotation of the view in degrees
+    pub rotation: f32
+}
+
+impl Camera {
+    /// Map a point of the IFS to image space, where the image covers [-1, 1] on both axes
+    pub fn view(&self, x: f32, y: f32) -> (f32, f32) {
+        let (sin, cos) = (-self.rotation.to_radians()).sin_cos();
+        let (dx, dy) = (x - self.center[0], y - self.center[1]);
+        final_transform(self.zoom * (cos * dx - sin * dy), self.zoom * (sin * dx + cos * dy))
+    }
+
+    /// Interpolate between two cameras. Zoom is interpolated geometrically so zooming in moves at a steady pace.
+    pub fn morph(&self, other: &Camera, pct: f32) -> Camera {
+        Camera::blend(&[*self, *other], &[1.0 - pct, pct])
+    }
+
+    /// Weighted combination of cameras, with `coefficients` summing to one. Zoom is combined geometrically.
+    pub fn blend(cameras: &[Camera], coefficients: &[f32]) -> Camera {
+        Camera {
+            center: [0, 1].map(|i| blend_f32(cameras.iter().map(|c| c.center[i]), coefficients)),
+            zoom: blend_f32(cameras.iter().map(|c| c.zoom.ln()), coefficients).exp(),
+            rotation: blend_f32(cameras.iter().map(|c| c.rotation), coefficients)
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera { center: [0.0, 0.0], zoom: 1.0, rotation: 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+    use crate::transform::final_transform;
+
+    #[test]
+    fn test_default_camera() {
+        assert_eq!(Camera::default().view(0.3, -0.7), final_transform(0.3, -0.7));
+    }
+
+    #[test]
+    fn test_camera_morph() {
+        let start = Camera::default();
+        let end = Camera { center: [1.0, 0.0], zoom: 4.0, rotation: 90.0 };
+        let halfway = start.morph(&end, 0.5);
+        assert!((halfway.zoom - 2.0).abs() < 1e-5);
+        assert_eq!(halfway.center, [0.5, 0.0]);
+        assert_eq!(halfway.rotation, 45.0);
+
+        // the center of the camera is the center of the image
+        let (x, y) = end.view(1.0, 0.0);
+        assert!(x.abs() < 1e-6 && y.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,126 @@
+//! checkpoints that let a long render be resumed after it is interrupted
+//!
+//! With `checkpoint_every` set in the evaluation settings, `Config::run` saves its accumulation buffer
+//! (see the `accumulation` module) every that many points, next to the image at `<path>.checkpoint.acc`.
+//! The config being rendered, with its seed filled in, goes to `<path>.checkpoint.json`.
+//! `barnsley evaluate --resume` picks the render up from there until all `num_points` are drawn:
+//!
+//! ```sh
+//! barnsley evaluate fern.json --resume
+//! ```
+//!
+//! Both files are removed once the image is saved.
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::config::Config;
+use crate::image::Image;
+
+/// Where the accumulation buffer and the config of a render to `image_path` are checkpointed
+pub fn checkpoint_paths(image_path: &str) -> (PathBuf, PathBuf) {
+    (PathBuf::from(format!("{}.checkpoint.acc", image_path)), PathBuf::from(format!("{}.checkpoint.json", image_path)))
+}
+
+/// Seed for the `chunk`-th batch of points in a render seeded with `seed`.
+///
+/// The first batch uses `seed` itself, so a render without checkpoints draws the same points as before.
+pub fn chunk_seed(seed: u64, chunk: usize) -> u64 {
+    seed ^ (chunk as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+impl Config {
+    /// Save `image` as the checkpoint of this config, which must have its seed filled in.
+    ///
+    /// Files are written under a temporary name and renamed, so a render killed while saving keeps the previous checkpoint.
+    pub fn save_checkpoint(&self, image: &Image) -> Result<(), String> {
+        let (buffer_path, config_path) = checkpoint_paths(&self.image_settings.path);
+        let (buffer_tmp, config_tmp) = (buffer_path.with_extension("acc.tmp"), config_path.with_extension("json.tmp"));
+        image.save_accumulation(&buffer_tmp)?;
+        fs::write(&config_tmp, serde_json::to_string(self).unwrap())
+            .map_err(|e| format!("could not write {}: {}", config_tmp.display(), e))?;
+        // the config goes first, the buffer is only trusted when a config sits next to it
+        rename(&config_tmp, &config_path)?;
+        rename(&buffer_tmp, &buffer_path)
+    }
+
+    /// Load the checkpoint of this config, returning the image rendered so far and the seed it was drawn with.
+    ///
+    /// `Ok(None)` means there is no checkpoint to resume from. A checkpoint saved by a config that differs from 
+    /// this one other than in its seed is an error, as the rest of the render would not match the points drawn so far.
+    pub fn load_checkpoint(&self) -> Result<Option<(Image, u64)>, String> {
+        let (buffer_path, config_path) = checkpoint_paths(&self.image_settings.path);
+        if !buffer_path.exists() || !config_path.exists() {
+            return Ok(None)
+        }
+        let data = fs::read_to_string(&config_path).map_err(|e| format!("could not open {}: {}", config_path.display(), e))?;
+        let saved: Config = serde_json::from_str(&data)
+            .map_err(|e| format!("could not parse the config in {}: {}", config_path.display(), e))?;
+        let seed = saved.evaluation_settings.seed
+            .ok_or_else(|| format!("{} has no seed to resume with", config_path.display()))?;
+        if without_seed(&saved) != without_seed(self) {
+            return Err(format!("{} was saved by a different config, remove it to start over", config_path.display()));
+        }
+
+        let image = Image::load_accumulation(&buffer_path)?;
+        if (image.width(), image.height()) != (self.image_settings.width as usize, self.image_settings.height as usize) {
+            return Err(format!("{} is {}x{} but the config renders {}x{}", buffer_path.display(), image.width(),
+                               image.height(), self.image_settings.width, self.image_settings.height));
+        }
+        Ok(Some((image, seed)))
+    }
+
+    /// Remove the checkpoint of this config, if there is one
+    pub fn remove_checkpoint(&self) {
+        let (buffer_path, config_path) = checkpoint_paths(&self.image_settings.path);
+        let _ = fs::remove_file(buffer_path);
+        let _ = fs::remove_file(config_path);
+    }
+}
+
+/// The config as JSON with its seed left out, for comparing configs
+pub(crate) fn without_seed(config: &Config) -> serde_json::Value {
+    let mut config = config.clone();
+    config.evaluation_settings.seed = None;
+    serde_json::to_value(config.migrate()).unwrap()
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), String> {
+    fs::rename(from, to).map_err(|e| format!("could not move {} to {}: {}", from.display(), to.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::checkpoint_paths;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let mut config = test_config("resume");
+        let path = config.image_settings.path.clone();
+        config.evaluation_settings.seed = Some(7);
+        config.evaluation_settings.checkpoint_every = Some(4);
+
+        // the checkpoint of a render interrupted after 8 of its 10 points
+        let mut partial = config.clone();
+        partial.evaluation_settings.num_points = 8;
+        let mut image = crate::image::Image::new(20, 20);
+        partial.render(&mut image);
+        config.save_checkpoint(&image).unwrap();
+        assert_eq!(config.load_checkpoint().unwrap().unwrap().0.samples(), 80);
+
+        // a checkpoint is only picked up by the config that saved it, whatever the seed
+        let mut reseeded = config.clone();
+        reseeded.evaluation_settings.seed = None;
+        assert!(reseeded.load_checkpoint().unwrap().is_some());
+        let mut changed = config.clone();
+        changed.evaluation_settings.num_iterations = 11;
+        assert!(changed.load_checkpoint().is_err());
+
+        config.clone().run_with_resume(true);
+        assert!(!checkpoint_paths(&path).0.exists());
+        let resumed = std::fs::read(&path).unwrap();
+
+        config.run();
+        assert_eq!(std::fs::read(&path).unwrap(), resumed);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,51 +1,441 @@
 //! core definition for an IFS run that can be serialized to a file
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use crate::transform::*;
 use crate::ifs::*;
-use crate::image::Image;
+use std::path::Path;
+use crate::image::{Background, HdrStage, Image, OutputFormat};
+use crate::metadata::{config_text, save_png_with_text};
+use crate::transition::Transitions;
+use crate::util::Color;
+use crate::checkpoint::chunk_seed;
+use crate::camera::Camera;
+use rand::{rngs::StdRng, SeedableRng};
+use std::time::{Duration, Instant};
+use ndarray::Array3;
+
+/// Version of the config and template file format written by this crate.
+/// 
+/// Files written before versioning was introduced have no `version` and are read as version 0. 
+/// Legacy field names are accepted through serde aliases, and fields added since are given defaults, 
+/// so older files keep loading. `Config::migrate` and `Template::migrate` bring them up to date.
+pub const SCHEMA_VERSION: u32 = 1;
 
 /// Configs are used to define an IFS run: the image settings used, the evaluation settings, and the transforms. 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Config {
+   /// file format version, see `SCHEMA_VERSION`
+   #[serde(default)]
+   pub version: u32,
+   /// size and location of the output image
    pub image_settings: ImageSettings,
+   /// how much work goes into the image
    pub evaluation_settings: EvaluationSettings,
-   pub transforms: Vec<Transform>
+   /// fully parameterized transforms of the IFS
+   #[schemars(length(min = 1))]
+   pub transforms: Vec<Transform>,
+   /// optional rules that make the choice of the next transform depend on the previous one
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub transitions: Option<Transitions>,
+   /// optional names of the transforms, in order, used to pair up transforms when morphing between IFSes
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub labels: Vec<Option<String>>
 }
 
 impl Config{
+   /// Bring a config read from an older file up to the current `SCHEMA_VERSION`.
+   /// 
+   /// Panics if the config comes from a newer version of the crate.
+   pub fn migrate(mut self) -> Config {
+      if self.version > SCHEMA_VERSION {
+         panic!("config has version {} but the newest supported version is {}", self.version, SCHEMA_VERSION);
+      }
+      self.version = SCHEMA_VERSION;
+      self
+   }
+
+   /// Build the IFS the config describes
+   pub fn ifs(&self) -> IFS {
+      let mut ifs = IFS::from_transforms(self.transforms.clone(), self.transitions.clone());
+      ifs.set_labels(self.labels.clone());
+      ifs
+   }
+
    /// Runs a config. 
+   /// 
+   /// PNGs get the config, with the seed that was used, stored inside them, see `Config::extract`. 
    pub fn run(self) {
-      let mut ifs = IFS::new();
+      self.run_with_resume(false)
+   }
+
+   /// Runs a config like `run`, continuing from its last checkpoint if `resume` is set and there is one.
+   /// 
+   /// Panics if the checkpoint can not be read. See the `checkpoint` module, and the `tiles` module for 
+   /// how images with `tile_rows` are rendered. 
+   pub fn run_with_resume(mut self, resume: bool) {
+    if let Some(tile_rows) = self.image_settings.tile_rows {
+      let path = self.image_settings.path.clone();
+      return self.run_tiled(tile_rows as usize, resume).unwrap_or_else(|e| panic!("could not render {}: {}", path, e))
+    }
+    let mut image = Image::new(self.image_settings.width as usize, self.image_settings.height as usize);
+    let checkpoint = if resume {
+      self.load_checkpoint().unwrap_or_else(|e| panic!("could not resume: {}", e))
+    } else {
+      None
+    };
+    if let Some((saved, seed)) = checkpoint {
+      image = saved;
+      self.evaluation_settings.seed = Some(seed);
+    }
+    let seed = *self.evaluation_settings.seed.get_or_insert_with(rand::random);
+    match self.image_settings.preview.clone() {
+      None => self.render(&mut image),
+      Some(preview) => {
+         let preview_path = preview.path.unwrap_or_else(|| format!("{}.preview.png", self.image_settings.path));
+         let (step, interval) = match preview.every {
+            PreviewInterval::Points(points) => (points as usize, None),
+            PreviewInterval::Seconds(seconds) => (PREVIEW_CHECK_POINTS, Some(Duration::from_secs_f32(seconds)))
+         };
+         let mut last_preview = Instant::now();
+         self.render_with_progress(&mut image, step, |image, _| {
+            if interval.is_none_or(|interval| last_preview.elapsed() >= interval) {
+               image.save(&preview_path, image.samples_per_pixel());
+               last_preview = Instant::now();
+            }
+         });
+      }
+    }
+    let samples_per_pixel = image.samples_per_pixel();
+
+    let path = Path::new(&self.image_settings.path);
+    let is_png = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    let background = self.image_settings.background();
+    let saved = match self.image_settings.output_format() {
+      format @ (OutputFormat::EightBit | OutputFormat::Png16) if is_png => {
+         let log_mean_luminance = image.log_mean_luminance(samples_per_pixel);
+         let display = image.to_display_image(samples_per_pixel, log_mean_luminance, format == OutputFormat::Png16, background);
+         save_png_with_text(path, &display, &config_text(&self, seed))
+      },
+      format => image.save_as(&self.image_settings.path, samples_per_pixel, format, self.image_settings.hdr_stage, background)
+    };
+    saved.unwrap_or_else(|e| panic!("could not save {}: {}", path.display(), e));
+    self.remove_checkpoint();
+   }
 
-      for transform in self.transforms.into_iter() {
-         ifs.add_transform(transform);
+   /// Draw the points of this config into `image`, skipping the ones already drawn into it, and 
+   /// save a checkpoint every `checkpoint_every` points. A random seed is used if the config has none. 
+   /// 
+   /// With a quality `target`, batches of `num_points` (or `checkpoint_every`) points are drawn until it is met, 
+   /// or until a batch lands no points in view. Panics if the target has no condition to stop at. 
+   pub fn render(&self, image: &mut Image) {
+      self.render_with_progress(image, usize::MAX, |_, _| {})
+   }
+
+   /// Render like `render`, calling `progress` with the image and the number of samples drawn so far 
+   /// after every `step` points. The points drawn do not depend on `step`. 
+   pub fn render_with_progress<F: FnMut(&Image, u64)>(&self, image: &mut Image, step: usize, mut progress: F) {
+      let mut config = self.clone();
+      let seed = *config.evaluation_settings.seed.get_or_insert_with(rand::random);
+      let ifs = self.ifs();
+      let num_points = self.evaluation_settings.num_points as usize;
+      let num_iterations = self.evaluation_settings.num_iterations as usize;
+      let checkpoint_every = self.evaluation_settings.checkpoint_every;
+      let target = self.evaluation_settings.target;
+      if target.is_some_and(|t| t.is_empty()) {
+         panic!("target needs at least one of samples_per_lit_pixel, relative_change or time_budget to stop at");
       }
 
-    let num_points = self.evaluation_settings.num_points as usize;
-    let num_iterations = self.evaluation_settings.num_iterations as usize;
+      let chunk = checkpoint_every.map_or(num_points, |n| n as usize).max(1);
+      let mut done = (image.samples() / num_iterations.max(1) as u64) as usize;
+      let started = Instant::now();
+      let mut previous: Option<(Array3<f32>, u64)> = None;
+      while target.is_some() || done < num_points {
+         let count = match target {
+            Some(_) => chunk,
+            None => chunk.min(num_points - done)
+         };
+         let mut rng = StdRng::seed_from_u64(chunk_seed(seed, done / chunk));
+         let hits_before = image.total_hits();
+         let mut drawn = 0;
+         while drawn < count {
+            let batch = step.max(1).min(count - drawn);
+            ifs.evaluate_with_rng(image, batch, num_iterations, &Camera::default(), &mut rng);
+            drawn += batch;
+            progress(image, image.samples());
+         }
+         done += count;
 
-    let mut image = Image::new(self.image_settings.width as usize, self.image_settings.height as usize);
-    ifs.evaluate(&mut image, num_points, num_iterations);
-    image.save(&self.image_settings.path, 1.max((num_points * num_iterations) / (image.height() * image.width())));
+         let finished = match target {
+            // a batch that lands nothing in view means no later one will either, so the target can never be met
+            Some(target) => image.total_hits() == hits_before || target.is_met(image, previous.as_ref(), started.elapsed()),
+            None => done >= num_points
+         };
+         if finished {
+            break
+         }
+         if checkpoint_every.is_some() {
+            config.save_checkpoint(image).unwrap_or_else(|e| panic!("could not save a checkpoint: {}", e));
+         }
+         if target.is_some_and(|t| t.relative_change.is_some()) {
+            previous = Some((image.data().clone(), image.samples()));
+         }
+      }
    }
 }
 
 /// Configuration of the image in an IFS run
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ImageSettings {
    /// how wide in pixels the generated image will be
+   #[schemars(range(min = 1))]
    pub width: u32,
    /// how tall in pixels the generated image will be    
+   #[schemars(range(min = 1))]
    pub height: u32,
    /// where to save the image
-   pub path: String
+   pub path: String,
+   /// file format of the image, chosen from the extension of `path` if missing
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub output_format: Option<OutputFormat>,
+   /// whether float formats hold values from before or after tone mapping
+   #[serde(default, skip_serializing_if = "is_default")]
+   pub hdr_stage: HdrStage,
+   /// previews to write while rendering, none if missing
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub preview: Option<PreviewSettings>,
+   /// render the image this many rows at a time to save memory, the whole image at once if missing. 
+   /// Every tile draws all the points, so a render in `n` tiles takes about `n` times as long. 
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   #[schemars(range(min = 1))]
+   pub tile_rows: Option<u32>,
+   /// color blended in after tone mapping where few or no points landed, black if missing
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub background: Option<Color>,
+   /// save with an alpha channel from the density of points instead of a background, for formats that support it
+   #[serde(default, skip_serializing_if = "is_default")]
+   pub transparent: bool
+}
+
+/// How often a progressive render writes a preview
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
+pub enum PreviewInterval {
+   /// after every this many points
+   Points(#[schemars(range(min = 1))] u32),
+   /// after at least this many seconds have passed
+   Seconds(#[schemars(range(min = 0.0))] f32)
+}
+
+/// Tone-mapped previews written while an image renders, so it can be watched taking shape
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct PreviewSettings {
+   /// how often the preview is written
+   pub every: PreviewInterval,
+   /// where to save the preview, `<path>.preview.png` next to the image if missing
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub path: Option<String>
+}
+
+/// Points drawn between checks of the clock when previews are written every few seconds
+const PREVIEW_CHECK_POINTS: usize = 100;
+
+pub(crate) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+   *value == T::default()
+}
+
+impl ImageSettings {
+   /// The file format to save in, `output_format` if given and otherwise chosen by the extension of `path`
+   pub fn output_format(&self) -> OutputFormat {
+      self.output_format.unwrap_or_else(|| OutputFormat::from_path(Path::new(&self.path)))
+   }
+
+   /// What shows through where few or no points landed, see `transparent` and `background`
+   pub fn background(&self) -> Background {
+      match (self.transparent, self.background) {
+         (true, _) => Background::Transparent,
+         (false, Some(color)) => Background::Color(color),
+         (false, None) => Background::default()
+      }
+   }
 }
 
 /// Configuration of the evaluation of an IFS run
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
 pub struct EvaluationSettings {
    /// how many iterations each point is evaluated for
+   #[schemars(range(min = 1))]
    pub num_iterations: u32,
    /// how many points are passed through the IFS
+   #[schemars(range(min = 1))]
    pub num_points: u32,
+   /// seed for the random choices, so a run can be repeated exactly. A random seed is used if missing.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub seed: Option<u64>,
+   /// how many points to draw between checkpoints that an interrupted run can resume from, none are saved if missing
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   #[schemars(range(min = 1))]
+   pub checkpoint_every: Option<u32>,
+   /// quality to render to, drawing batches of `num_points` points until it is reached, instead of `num_points` in total
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub target: Option<QualityTarget>,
+}
+
+/// When a render is good enough to stop. It stops once every quality target given is met, or when the time budget runs out.
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Default)]
+pub struct QualityTarget {
+   /// mean number of samples in the pixels that were hit at all
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   #[schemars(range(min = 0.0))]
+   pub samples_per_lit_pixel: Option<f32>,
+   /// how much the last batch may change the image, relative to its total brightness, e.g. 0.01 for one percent
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   #[schemars(range(min = 0.0))]
+   pub relative_change: Option<f32>,
+   /// seconds to render for at most, even if the quality targets are not met
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   #[schemars(range(min = 0.0))]
+   pub time_budget: Option<f32>,
+}
+
+impl QualityTarget {
+   /// True if no condition to stop at is given, a render with such a target would never end
+   pub fn is_empty(&self) -> bool {
+      self.samples_per_lit_pixel.is_none() && self.relative_change.is_none() && self.time_budget.is_none()
+   }
+
+   /// Whether a render that has taken `elapsed` so far can stop. `previous` holds the color and sample count 
+   /// of `image` before the last batch, the relative change is never met without it. 
+   pub fn is_met(&self, image: &Image, previous: Option<&(Array3<f32>, u64)>, elapsed: Duration) -> bool {
+      if self.time_budget.is_some_and(|budget| elapsed.as_secs_f32() >= budget) {
+         return true
+      }
+      let samples_met = self.samples_per_lit_pixel.is_none_or(|target| image.mean_hits_per_lit_pixel() >= target);
+      let change_met = self.relative_change.is_none_or(|target| {
+         previous.is_some_and(|(data, samples)| image.relative_change(data, *samples) <= target)
+      });
+      let has_quality_target = self.samples_per_lit_pixel.is_some() || self.relative_change.is_some();
+      has_quality_target && samples_met && change_met
+   }
+}
+
+impl EvaluationSettings {
+   /// Rough number of samples landing in each pixel of a `width` by `height` image, before any are drawn. 
+   /// Once an image is rendered, `Image::samples_per_pixel` counts the samples actually drawn.
+   pub fn samples_per_pixel(&self, width: usize, height: usize) -> usize {
+      1.max((self.num_points as usize * self.num_iterations as usize) / (width * height))
+   }
+}
+
+/// A small Sierpinski triangle config for tests, 20x20 pixels from 10 points of 10 iterations each, 
+/// saving to a file in the temporary directory named after `name` and this process
+#[cfg(test)]
+pub(crate) fn test_config(name: &str) -> Config {
+   let path = crate::util::test_path(&format!("{}.png", name));
+   let mut config = crate::presets::Preset::SierpinskiTriangle.config();
+   config.image_settings.path = path.to_str().unwrap().to_string();
+   config.image_settings.width = 20;
+   config.image_settings.height = 20;
+   config.evaluation_settings.num_points = 10;
+   config.evaluation_settings.num_iterations = 10;
+   config
+}
+
+#[cfg(test)]
+mod tests {
+   use std::time::Duration;
+   use crate::config::{test_config, Config, QualityTarget, SCHEMA_VERSION};
+   use crate::image::Image;
+   use crate::transform::Transform;
+
+   #[test]
+   fn test_legacy_config_migrates() {
+      let legacy = r#"{"image_settings": {"width": 10, "height": 10, "path": "legacy.png"},
+         "evaluation_settings": {"num_iterations": 10, "num_points": 10},
+         "transforms": [{"AffineTransform": {"a": 0.5, "b": 0.0, "c": 0.0, "d": 0.5,
+            "xshift": 1.0, "yshift": 2.0, "base_color": {"r": 1.0, "g": 0.0, "b": 0.0}, "weight": 1.0}}]}"#;
+      let config: Config = serde_json::from_str(legacy).unwrap();
+      assert_eq!(config.version, 0);
+
+      let config = config.migrate();
+      assert_eq!(config.version, SCHEMA_VERSION);
+      match config.transforms[0] {
+         Transform::AffineTransform(t) => assert_eq!((t.x_shift, t.y_shift), (1.0, 2.0)),
+         _ => panic!("expected an AffineTransform")
+      }
+      assert!(serde_json::to_string(&config).unwrap().contains("\"x_shift\":1.0"));
+   }
+
+   #[test]
+   fn test_migrated_template_leaves_out_defaults() {
+      let legacy = r#"image_settings = {width = 10, height = 10, path = "legacy.png"}
+         evaluation_settings = {num_iterations = 10, num_points = 10}
+         random_transforms = ["AffineTransform"]"#;
+      let template: crate::template::Template = toml::from_str(legacy).unwrap();
+      let migrated = toml::to_string(&template.migrate()).unwrap();
+      assert!(!migrated.contains("random_transitions"));
+      assert!(!migrated.contains("transparent"));
+   }
+   #[test]
+   fn test_render_with_progress() {
+      let mut config = test_config("render_with_progress");
+      config.evaluation_settings.seed = Some(3);
+
+      let mut progressive = Image::new(20, 20);
+      let mut seen = vec![];
+      config.render_with_progress(&mut progressive, 4, |image, samples| {
+         assert_eq!(image.samples(), samples);
+         seen.push(samples);
+      });
+      assert_eq!(seen, vec![40, 80, 100]);
+
+      // previews do not change the points drawn
+      let mut image = Image::new(20, 20);
+      config.render(&mut image);
+      assert_eq!(image.data(), progressive.data());
+      assert_eq!(image.samples_per_pixel(), 1);
+   }
+   #[test]
+   fn test_quality_target() {
+      let mut config = test_config("quality_target");
+      config.evaluation_settings.target = Some(QualityTarget{samples_per_lit_pixel: Some(3.0), ..Default::default()});
+
+      let mut image = Image::new(20, 20);
+      config.render(&mut image);
+      assert!(image.mean_hits_per_lit_pixel() >= 3.0);
+      assert_eq!(image.samples() % 100, 0);
+
+      // an unchanged image has converged, but only once there is a pass to compare with
+      let target = QualityTarget{relative_change: Some(0.01), ..Default::default()};
+      let previous = (image.data().clone(), image.samples());
+      assert!(!target.is_met(&image, None, Duration::ZERO));
+      assert!(target.is_met(&image, Some(&previous), Duration::ZERO));
+
+      let budget = QualityTarget{time_budget: Some(1.0), ..Default::default()};
+      assert!(!budget.is_met(&image, None, Duration::ZERO));
+      assert!(budget.is_met(&image, None, Duration::from_secs(2)));
+   }
+
+   #[test]
+   fn test_unreachable_target_stops() {
+      let mut config = test_config("unreachable_target");
+      config.evaluation_settings.target = Some(QualityTarget{samples_per_lit_pixel: Some(3.0), ..Default::default()});
+      // move the attractor far out of view
+      for transform in config.transforms.iter_mut() {
+         if let Transform::AffineTransform(t) = transform {
+            t.x_shift += 100.0;
+         }
+      }
+
+      let mut image = Image::new(20, 20);
+      config.render(&mut image);
+      assert_eq!(image.total_hits(), 0);
+      assert_eq!(image.samples(), 100);
+   }
+
+   #[test]
+   #[should_panic(expected = "target needs at least one")]
+   fn test_empty_target_is_rejected() {
+      let mut config = test_config("empty_target");
+      config.evaluation_settings.target = Some(QualityTarget::default());
+      config.render(&mut Image::new(20, 20));
+   }
 }
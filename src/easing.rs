@@ -0,0 +1,133 @@
+//! easing curves that shape the timing of a morph between keyframes
+//!
+//! An easing curve maps how far along a segment of an animation the current frame is,
+//! from 0 to 1, to how far the morph between the two keyframes has progressed, also from 0 to 1.
+//! `Linear` moves at a constant rate and starts and stops abruptly, while the others speed up
+//! and slow down gradually.
+use std::f32::consts::PI;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Number of bisection steps used to invert the x coordinate of a cubic Bézier curve
+const BEZIER_BISECTION_STEPS: usize = 30;
+
+/// Shape of the timing of a morph between two keyframes
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Default)]
+pub enum Easing {
+    /// constant speed
+    #[default]
+    Linear,
+    /// starts and stops gently, 3t^2 - 2t^3
+    Smoothstep,
+    /// starts slowly and speeds up
+    EaseInCubic,
+    /// starts quickly and slows down
+    EaseOutCubic,
+    /// starts and stops slowly
+    EaseInOutCubic,
+    /// starts and stops slowly following half a cosine wave
+    Sine,
+    /// cubic Bézier curve from (0, 0) to (1, 1) with control points (x1, y1) and (x2, y2),
+    /// like CSS `cubic-bezier`
+    CubicBezier {
+        #[schemars(range(min = 0.0, max = 1.0))]
+        x1: f32,
+        y1: f32,
+        #[schemars(range(min = 0.0, max = 1.0))]
+        x2: f32,
+        y2: f32
+    }
+}
+
+impl Easing {
+    /// Map progress `t` through a segment, from 0 to 1, to progress of the morph
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            Easing::Linear => t,
+            Easing::Smoothstep => t * t * (3.0 - 2.0 * t),
+            Easing::EaseInCubic => t * t * t,
+            Easing::EaseOutCubic => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOutCubic => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+            },
+            Easing::Sine => (1.0 - (PI * t).cos()) / 2.0,
+            Easing::CubicBezier { x1, y1, x2, y2 } => {
+                // x(s) is increasing when x1 and x2 are in [0, 1], so bisect for the s where x(s) = t
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..BEZIER_BISECTION_STEPS {
+                    let s = (low + high) / 2.0;
+                    if bezier(x1, x2, s) < t {
+                        low = s;
+                    } else {
+                        high = s;
+                    }
+                }
+                bezier(y1, y2, (low + high) / 2.0)
+            }
+        }
+    }
+}
+
+/// One coordinate of a cubic Bézier curve from 0 to 1 with control values `p1` and `p2`
+fn bezier(p1: f32, p2: f32, s: f32) -> f32 {
+    let inverse = 1.0 - s;
+    3.0 * inverse * inverse * s * p1 + 3.0 * inverse * s * s * p2 + s * s * s
+}
+
+/// How the animation path runs through its keyframes
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Default)]
+pub enum Interpolation {
+    /// morph straight from each keyframe to the next, changing direction abruptly at keyframes
+    #[default]
+    Linear,
+    /// a Catmull-Rom spline through every keyframe, so motion carries smoothly through them
+    CatmullRom
+}
+
+/// Coefficients of the four keyframes around a uniform Catmull-Rom segment at `t`, from 0 to 1.
+/// The segment runs between the second and third keyframes, and the coefficients sum to one.
+pub fn catmull_rom_coefficients(t: f32) -> [f32; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::easing::{catmull_rom_coefficients, Easing};
+
+    #[test]
+    fn test_easing_endpoints() {
+        let easings = [Easing::Linear, Easing::Smoothstep, Easing::EaseInCubic, Easing::EaseOutCubic,
+                       Easing::EaseInOutCubic, Easing::Sine,
+                       Easing::CubicBezier { x1: 0.25, y1: 0.1, x2: 0.25, y2: 1.0 }];
+        for easing in easings {
+            assert!(easing.apply(0.0).abs() < 1e-5);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-5);
+            assert!(easing.apply(0.3) <= easing.apply(0.7));
+        }
+    }
+
+    #[test]
+    fn test_linear_bezier() {
+        let easing = Easing::CubicBezier { x1: 0.0, y1: 0.0, x2: 1.0, y2: 1.0 };
+        for t in [0.1, 0.25, 0.5, 0.9] {
+            assert!((easing.apply(t) - t).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_catmull_rom_coefficients() {
+        assert_eq!(catmull_rom_coefficients(0.0), [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(catmull_rom_coefficients(1.0), [0.0, 0.0, 1.0, 0.0]);
+        assert!((catmull_rom_coefficients(0.37).iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,212 @@
+//! writing animations straight to animated image formats
+//!
+//! Frames are written one at a time as they are rendered, so whole animations never
+//! need to be held in memory. The format is chosen from the file extension:
+//! `.gif` for animated GIF, `.png` or `.apng` for animated PNG, and `.webp` for animated WebP.
+//!
+//! GIF frames are quantized to a 256 color palette by the encoder. WebP frames are
+//! encoded losslessly.
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+
+/// Speed of the GIF palette quantization, from 1 (best quality) to 30 (fastest)
+const GIF_QUANTIZATION_SPEED: i32 = 10;
+
+/// Animated image formats an animation can be encoded to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    WebP,
+}
+
+impl AnimationFormat {
+    /// Pick the format from the extension of `path`, `None` if it is not an animated format
+    pub fn from_path(path: &Path) -> Option<AnimationFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "png" | "apng" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::WebP),
+            _ => None
+        }
+    }
+}
+
+/// Writes frames to an animated image file one at a time
+pub struct AnimationWriter {
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Gif(GifEncoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+    WebP{file: BufWriter<File>, width: u32, height: u32},
+}
+
+impl AnimationWriter {
+    /// Start writing an animation of `num_frames` frames to `path`.
+    ///
+    /// `loop_count` is the number of times the animation plays, zero loops forever.
+    pub fn new(path: &Path, format: AnimationFormat, width: u32, height: u32,
+               num_frames: u32, loop_count: u16) -> AnimationWriter {
+        let file = BufWriter::new(File::create(path)
+            .unwrap_or_else(|e| panic!("could not create {}: {}", path.display(), e)));
+
+        let encoder = match format {
+            AnimationFormat::Gif => {
+                let mut encoder = GifEncoder::new_with_speed(file, GIF_QUANTIZATION_SPEED);
+                // a gif without a repeat extension plays once, and a repeat count counts replays after the first
+                match loop_count {
+                    0 => encoder.set_repeat(Repeat::Infinite).unwrap(),
+                    1 => {},
+                    n => encoder.set_repeat(Repeat::Finite(n - 1)).unwrap()
+                }
+                Encoder::Gif(encoder)
+            },
+            AnimationFormat::Apng => {
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(num_frames, loop_count as u32).unwrap();
+                Encoder::Apng(encoder.write_header().unwrap())
+            },
+            AnimationFormat::WebP => {
+                let mut file = file;
+                write_webp_header(&mut file, width, height, loop_count).unwrap();
+                Encoder::WebP{file, width, height}
+            }
+        };
+        AnimationWriter{encoder}
+    }
+
+    /// Append a frame that is displayed for `delay_ms` milliseconds
+    pub fn write_frame(&mut self, frame: &RgbImage, delay_ms: u32) {
+        match &mut self.encoder {
+            Encoder::Gif(encoder) => {
+                let rgba = DynamicImage::ImageRgb8(frame.clone()).into_rgba8();
+                encoder.encode_frame(Frame::from_parts(rgba, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))).unwrap();
+            },
+            Encoder::Apng(writer) => {
+                writer.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000).unwrap();
+                writer.write_image_data(frame.as_raw()).unwrap();
+            },
+            Encoder::WebP{file, width, height} => {
+                write_webp_frame(file, frame, *width, *height, delay_ms).unwrap();
+            }
+        }
+    }
+
+    /// Finish the file, this must be called after the last frame
+    pub fn finish(self) {
+        match self.encoder {
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Apng(writer) => writer.finish().unwrap(),
+            Encoder::WebP{mut file, ..} => {
+                // the RIFF header holds the size of everything after it, known only now
+                let size = file.stream_position().unwrap() as u32 - 8;
+                file.seek(SeekFrom::Start(4)).unwrap();
+                file.write_all(&size.to_le_bytes()).unwrap();
+                file.flush().unwrap();
+            }
+        }
+    }
+}
+
+/// Write a chunk of a RIFF file, padded to an even length
+fn write_riff_chunk<W: Write>(w: &mut W, name: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    w.write_all(name)?;
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(data)?;
+    if data.len() % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+/// Write the RIFF header with a placeholder size, then the VP8X and ANIM chunks of an animated WebP
+fn write_webp_header<W: Write>(w: &mut W, width: u32, height: u32, loop_count: u16) -> std::io::Result<()> {
+    w.write_all(b"RIFF")?;
+    w.write_all(&0u32.to_le_bytes())?;
+    w.write_all(b"WEBP")?;
+
+    let mut vp8x = vec![0x02, 0, 0, 0]; // animation flag then reserved bytes
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    write_riff_chunk(w, b"VP8X", &vp8x)?;
+
+    let mut anim = vec![0, 0, 0, 255]; // opaque black background, in BGRA
+    anim.extend_from_slice(&loop_count.to_le_bytes());
+    write_riff_chunk(w, b"ANIM", &anim)
+}
+
+/// Write one frame of an animated WebP as an ANMF chunk wrapping a lossless VP8L bitstream
+fn write_webp_frame<W: Write>(w: &mut W, frame: &RgbImage, width: u32, height: u32, delay_ms: u32) -> std::io::Result<()> {
+    let mut still = vec![];
+    image_webp::WebPEncoder::new(&mut still)
+        .encode(frame.as_raw(), width, height, image_webp::ColorType::Rgb8)
+        .map_err(std::io::Error::other)?;
+
+    let mut anmf = vec![0; 6]; // x and y offsets of the frame
+    anmf.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    anmf.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    anmf.extend_from_slice(&delay_ms.min(0xFF_FFFF).to_le_bytes()[..3]);
+    anmf.push(0x02); // do not blend with the previous frame, do not dispose
+    // a simple WebP file is a 12 byte RIFF header followed by the VP8L chunk
+    anmf.extend_from_slice(&still[12..]);
+    write_riff_chunk(w, b"ANMF", &anmf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+    use image::{AnimationDecoder, Rgb, RgbImage};
+    use image::codecs::gif::GifDecoder;
+    use image::codecs::png::PngDecoder;
+    use image::codecs::webp::WebPDecoder;
+    use crate::encode::{AnimationFormat, AnimationWriter};
+    use crate::util::test_path;
+
+    fn write_test_animation(path: &Path) {
+        let format = AnimationFormat::from_path(path).unwrap();
+        let mut writer = AnimationWriter::new(path, format, 4, 3, 2, 0);
+        writer.write_frame(&RgbImage::from_pixel(4, 3, Rgb([255, 0, 0])), 100);
+        writer.write_frame(&RgbImage::from_pixel(4, 3, Rgb([0, 0, 255])), 200);
+        writer.finish();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = test_path("round_trip.gif");
+        write_test_animation(&path);
+        let frames = GifDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap()
+            .into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].buffer().get_pixel(0, 0)[2], 255);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let path = test_path("round_trip.png");
+        write_test_animation(&path);
+        let frames = PngDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap()
+            .apng().unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].delay().numer_denom_ms(), (200, 1));
+
+        std::fs::remove_file(&path).unwrap();
+
+        let path = test_path("round_trip.webp");
+        write_test_animation(&path);
+        let frames = WebPDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap()
+            .into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buffer().get_pixel(3, 2).0, [255, 0, 0, 255]);
+        assert_eq!(frames[1].delay().numer_denom_ms(), (200, 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
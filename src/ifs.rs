@@ -5,8 +5,37 @@ use rand_distr::Distribution;
 use crate::util::*;
 use crate::transform::*;
 use crate::image::*;
+use crate::transition::Transitions;
+use crate::camera::Camera;
 
 
+/// Receives the points of the chaos game, see `IFS::evaluate_into`
+pub trait PointSink {
+    /// Take the point `(x, y)` of the IFS, with its `color`, reached by applying transform number `transform`
+    fn add_point(&mut self, x: f32, y: f32, color: Color, transform: usize);
+}
+
+/// Draws points into an image as seen through a camera
+struct ViewedImage<'a> {
+    image: &'a mut Image,
+    camera: &'a Camera
+}
+
+impl PointSink for ViewedImage<'_> {
+    fn add_point(&mut self, x: f32, y: f32, color: Color, _transform: usize) {
+        let (fx, fy) = self.camera.view(x, y);
+        if !(-1.0..1.0).contains(&fx) || !(-1.0..1.0).contains(&fy) {
+            // out of view, the cast to usize would otherwise pile these onto the top and left edges
+            return
+        }
+        let (width, height) = self.image.canvas_size();
+        let x = ((fx + 1.0) * (width as f32 / 2.0)) as usize;
+        let y = ((fy + 1.0) * (height as f32 / 2.0)) as usize;
+
+        self.image.add_radiance(x, y, color);
+    }
+}
+
 /// Iterated function system
 pub struct IFS {
     /// transforms used in the iterated function system
@@ -16,7 +45,15 @@ pub struct IFS {
     /// the total weight of all the transforms in the IFS, stored for efficiency
     total_weight: f32,
     /// the distribution used in selecting a random transform, stored for efficiency instead of generating on the fly
-    distribution: WeightedIndex<f32>
+    distribution: WeightedIndex<f32>,
+    /// optional rules making the choice of transform depend on the previous one
+    transitions: Option<Transitions>,
+    /// one distribution per previous transform when `transitions` is set, stored for efficiency
+    transition_distributions: Vec<WeightedIndex<f32>>,
+    /// optional name of each transform, used to pair up transforms when morphing
+    labels: Vec<Option<String>>,
+    /// for transforms partway through a cross-fade from `morph`, the transform being faded to and how far along it is
+    cross_fades: Vec<Option<(Transform, f32)>>
 }
 
 impl IFS{
@@ -25,7 +62,29 @@ impl IFS{
         IFS{transforms: vec![],
         num_transforms: 0,
         total_weight: 0.,
-        distribution: WeightedIndex::new([1.]).unwrap()}
+        distribution: WeightedIndex::new([1.]).unwrap(),
+        transitions: None,
+        transition_distributions: vec![],
+        labels: vec![],
+        cross_fades: vec![]}
+    }
+
+    /// Define an IFS from a list of transforms and optional transition rules
+    /// 
+    /// Panics if the transitions do not fit the transforms.
+    pub fn from_transforms(transforms: Vec<Transform>, transitions: Option<Transitions>) -> IFS {
+        let mut ifs = IFS::new();
+        if !transforms.is_empty() {
+            // built all at once since some of the weights may be zero, like transforms fading out of a morph
+            ifs.distribution = WeightedIndex::new(transforms.iter().map(|t| t.get_weight())).unwrap();
+        }
+        ifs.num_transforms = transforms.len();
+        ifs.total_weight = transforms.iter().map(|t| t.get_weight()).sum();
+        ifs.labels = vec![None; transforms.len()];
+        ifs.cross_fades = vec![None; transforms.len()];
+        ifs.transforms = transforms;
+        ifs.set_transitions(transitions);
+        ifs
     }
 
     /// Update the IFS to new random weights and random parameters for each transform
@@ -33,6 +92,8 @@ impl IFS{
         self.transforms = self.transforms.iter().map(|t| transform_from_str(t.get_name())).collect();
         self.distribution = WeightedIndex::new(self.transforms.iter().map(|t| t.get_weight())).unwrap(); 
         self.total_weight = self.transforms.iter().map(|t| t.get_weight()).sum();
+        self.cross_fades = vec![None; self.len()];
+        self.update_transition_distributions();
     }
 
     /// Get the number of transforms in an IFS
@@ -69,8 +130,67 @@ impl IFS{
         }
     }
 
+    /// Get the label of the transform at index i, if it has one
+    pub fn get_label(&self, i: usize) -> Option<&str> {
+        self.labels.get(i).expect("i is greater than the number of transforms").as_deref()
+    }
+
+    /// Name the transform at index i. Labels pair up transforms when morphing to another IFS.
+    pub fn set_label(&mut self, i: usize, label: Option<String>) {
+        *self.labels.get_mut(i).expect("i is greater than the number of transforms") = label;
+    }
+
+    /// Label the transforms in order, any transforms past the end of `labels` are left unlabeled
+    pub fn set_labels(&mut self, labels: Vec<Option<String>>) {
+        for (i, label) in labels.into_iter().enumerate() {
+            self.set_label(i, label);
+        }
+    }
+
+    /// The weight of every transform, in order
+    pub fn weights(&self) -> Vec<f32> {
+        self.transforms.iter().map(|t| t.get_weight()).collect()
+    }
+
+    /// Get the transition rules, if this is a recurrent IFS
+    pub fn get_transitions(&self) -> Option<&Transitions> {
+        self.transitions.as_ref()
+    }
+
+    /// Make this a recurrent IFS where the next transform depends on the previous one.
+    /// Passing `None` goes back to choosing every transform independently.
+    ///
+    /// Panics if the transitions do not fit the current transforms.
+    ///
+    /// ```rust
+    /// use barnsley::{transform::AffineTransform, ifs::IFS, transition::Transitions};
+    ///
+    /// let mut my_ifs = IFS::new();
+    /// my_ifs.add_transform(AffineTransform::random().into());
+    /// my_ifs.add_transform(AffineTransform::random().into());
+    /// my_ifs.set_transitions(Some(Transitions::Matrix(vec![vec![0.0, 1.0], vec![0.5, 0.5]])));
+    /// ```
+    pub fn set_transitions(&mut self, transitions: Option<Transitions>) {
+        if let Some(t) = &transitions {
+            if let Some(error) = t.validate(&self.weights()).first() {
+                panic!("invalid transitions: {}", error);
+            }
+        }
+        self.transitions = transitions;
+        self.update_transition_distributions();
+    }
+
+    fn update_transition_distributions(&mut self) {
+        self.transition_distributions = match &self.transitions {
+            Some(t) => t.distributions(&self.weights()),
+            None => vec![]
+        };
+    }
+
     /// Add a transform to the IFS
     /// 
+    /// Any transition rules are removed since they no longer match the transforms.
+    /// 
     /// ```rust
     /// use barnsley::{transform::AffineTransform, ifs::IFS};
     /// 
@@ -80,23 +200,34 @@ impl IFS{
     pub fn add_transform(&mut self, transform: Transform) {
         self.total_weight += transform.get_weight();
         self.transforms.insert(self.num_transforms, transform);
+        self.labels.push(None);
+        self.cross_fades.push(None);
         self.num_transforms += 1;
         self.distribution = WeightedIndex::new(self.transforms.iter().map(|t| t.get_weight())).unwrap(); 
+        self.set_transitions(None);
     }
 
     /// Remove a transform from an IFS
+    /// 
+    /// Any transition rules are removed since they no longer match the transforms.
     pub fn delete_transform(&mut self, index: usize) {
         let transform = self.transforms.get(index).unwrap();
         self.total_weight -= transform.get_weight();
         self.num_transforms -= 1;
         self.transforms.remove(index);
+        self.labels.remove(index);
+        self.cross_fades.remove(index);
         self.distribution = WeightedIndex::new(self.transforms.iter().map(|t| t.get_weight())).unwrap(); 
+        self.set_transitions(None);
     }
 
-    /// Select a transform at random according to the weighting 
-    fn choose_transform(&self) -> &Transform {
-        let mut rng = thread_rng();
-        self.transforms.get(self.distribution.sample(&mut rng)).unwrap()
+    /// Select the index of a transform at random according to the weighting, 
+    /// or according to the transition rules given the `previous` transform for a recurrent IFS
+    fn choose_transform<R: Rng>(&self, previous: Option<usize>, rng: &mut R) -> usize {
+        match previous.and_then(|i| self.transition_distributions.get(i)) {
+            Some(distribution) => distribution.sample(rng),
+            None => self.distribution.sample(rng)
+        }
     }   
 
     /// Evaluate a transform 
@@ -110,52 +241,244 @@ impl IFS{
     /// my_ifs.evaluate(&mut image, 1000, 1000);
     /// ```
     pub fn evaluate(&self, image: &mut Image, num_points: usize, num_iterations: usize) {
+        self.evaluate_with_camera(image, num_points, num_iterations, &Camera::default())
+    }
+
+    /// Evaluate like `evaluate`, viewing the IFS through `camera` instead of the default view
+    pub fn evaluate_with_camera(&self, image: &mut Image, num_points: usize, num_iterations: usize, camera: &Camera) {
+        self.evaluate_with_rng(image, num_points, num_iterations, camera, &mut rand::thread_rng())
+    }
+
+    /// Evaluate like `evaluate`, drawing every random choice from `seed` so the result can be repeated exactly
+    pub fn evaluate_with_seed(&self, image: &mut Image, num_points: usize, num_iterations: usize, seed: u64) {
+        self.evaluate_with_rng(image, num_points, num_iterations, &Camera::default(), &mut StdRng::seed_from_u64(seed))
+    }
+
+    /// Evaluate like `evaluate_with_camera`, drawing every random choice from `rng`. 
+    /// 
+    /// Splitting a render into several calls with the same `rng` draws the same points as one call. 
+    pub fn evaluate_with_rng<R: Rng>(&self, image: &mut Image, num_points: usize, num_iterations: usize, 
+                                     camera: &Camera, rng: &mut R) {
+        image.add_samples((num_points * num_iterations) as u64);
+        self.evaluate_into(&mut ViewedImage{image, camera}, num_points, num_iterations, rng)
+    }
+
+    /// Run the chaos game for `num_points` points of `num_iterations` iterations each, handing every point 
+    /// it reaches to `sink` instead of drawing it into an image
+    pub fn evaluate_into<S: PointSink, R: Rng>(&self, sink: &mut S, num_points: usize, num_iterations: usize, rng: &mut R) {
         for _ in 0..num_points {
-            self.single_point_evaluation(image, num_iterations)
+            self.single_point_evaluation(sink, num_iterations, rng)
         }
     }
 
-    fn single_point_evaluation(&self, image: &mut Image, num_iterations: usize) {
-        let mut rng = rand::thread_rng();
-
+    fn single_point_evaluation<S: PointSink, R: Rng>(&self, sink: &mut S, num_iterations: usize, rng: &mut R) {
         let mut px: f32 = rng.gen::<f32>() * 2. - 1.;
         let mut py: f32 = rng.gen::<f32>() * 2. - 1.;
 
         let mut color = Color{r: 0.0, g: 0.0, b: 0.0};
+        let mut previous = None;
 
         for _ in 0..num_iterations {
-            let t = self.choose_transform();
-            let new_point = t.transform_point(Point{x: px, y: py});
-            px = new_point.x;
-            py = new_point.y;
-            color = t.transform_color(color);
-
-            let (fx, fy) = final_transform(px, py);
-            let x = ((fx + 1.0) * (image.width() as f32 / 2.0)) as usize;
-            let y = ((fy + 1.0) * (image.height() as f32 / 2.0)) as usize;
-            
-            image.add_radiance(x, y, color);
+            let index = self.choose_transform(previous, rng);
+            previous = Some(index);
+            let t = self.transforms.get(index).unwrap();
+            let new_point = t.transform_point_with_rng(Point{x: px, y: py}, rng);
+            let new_color = t.transform_color(color);
+            (px, py, color) = match self.cross_fades[index] {
+                Some((to, pct)) => {
+                    let to_point = to.transform_point_with_rng(Point{x: px, y: py}, rng);
+                    (lerp_f32(new_point.x, to_point.x, pct), lerp_f32(new_point.y, to_point.y, pct),
+                     lerp_color(new_color, to.transform_color(color), pct))
+                },
+                None => (new_point.x, new_point.y, new_color)
+            };
+            sink.add_point(px, py, color, index);
         }
     }
 
+    /// Morph `pct` of the way from this IFS to `other`, which may have different transforms.
+    /// 
+    /// Transforms are paired up across the two IFSes, see `pair_transforms`. Pairs of the same type are 
+    /// interpolated, pairs of different types cross-fade by blending their outputs, and transforms left 
+    /// without a partner fade in or out by weight. Cross-fades already in either IFS are not carried over.
+    /// 
+    /// ```rust
+    /// use barnsley::ifs::IFS;
+    /// 
+    /// let halfway = IFS::barnsley_fern().morph(&IFS::sierpinski_triangle(), 0.5);
+    /// assert_eq!(halfway.len(), 4);
+    /// ```
     pub fn morph(&self, other: &Self, pct: f32) -> Self {
-           if !self.check_transforms_match(other) {
-               panic!("Transforms must match");
-           } else {
-               let mut out = IFS::new();
-               for i in 0..self.transforms.len() {
-                    let a = *self.transforms.get(i).unwrap();
-                    let b = *other.transforms.get(i).unwrap();
-                    let new = a.morph(b, pct);
-                    out.add_transform(new);
-               }
-               out
-           }
-   }
+        let pairs = self.pair_transforms(other);
+        let mut transforms = vec![];
+        let mut cross_fades = vec![];
+        let mut labels = vec![];
+        for &(from, to) in &pairs {
+            let (transform, cross_fade) = match (from, to) {
+                (Some(i), Some(j)) => {
+                    let a = self.get_transform(i);
+                    let b = other.get_transform(j);
+                    if a.get_name() == b.get_name() {
+                        (a.morph(b, pct), None)
+                    } else {
+                        (a.with_weight(lerp_f32(a.get_weight(), b.get_weight(), pct)), Some((b, pct)))
+                    }
+                },
+                (Some(i), None) => {
+                    let a = self.get_transform(i);
+                    (a.with_weight(a.get_weight() * (1.0 - pct)), None)
+                },
+                (None, Some(j)) => {
+                    let b = other.get_transform(j);
+                    (b.with_weight(b.get_weight() * pct), None)
+                },
+                (None, None) => unreachable!("every pair has at least one transform")
+            };
+            transforms.push(transform);
+            cross_fades.push(cross_fade);
+            labels.push(from.and_then(|i| self.labels[i].clone())
+                .or_else(|| to.and_then(|j| other.labels[j].clone())));
+        }
+        let mut out = IFS::from_transforms(transforms, None);
+        out.cross_fades = cross_fades;
+        out.labels = labels;
+
+        if self.transitions.is_some() || other.transitions.is_some() {
+            let from = self.embedded_transitions(pairs.iter().map(|p| p.0).collect());
+            let to = other.embedded_transitions(pairs.iter().map(|p| p.1).collect());
+            let weights = out.weights();
+            out.set_transitions(from.morph(&to, &weights, &weights, pct));
+        }
+        out
+    }
+
+    /// Pair up the transforms of this IFS with those of `other`, as indices into each. 
+    /// 
+    /// Transforms sharing a label are paired first, then transforms of the same type in order, 
+    /// then whatever is left in order. Transforms of whichever IFS has more are left without a partner. 
+    /// Pairs follow the order of this IFS, followed by the unpaired transforms of `other`.
+    pub fn pair_transforms(&self, other: &Self) -> Vec<(Option<usize>, Option<usize>)> {
+        let mut partners: Vec<Option<usize>> = vec![None; self.len()];
+        let mut taken = vec![false; other.len()];
+        let mut pair_up = |matches: &dyn Fn(usize, usize) -> bool| {
+            for (i, partner) in partners.iter_mut().enumerate() {
+                if partner.is_some() {
+                    continue
+                }
+                if let Some(j) = (0..other.len()).find(|&j| !taken[j] && matches(i, j)) {
+                    *partner = Some(j);
+                    taken[j] = true;
+                }
+            }
+        };
+        pair_up(&|i, j| self.labels[i].is_some() && self.labels[i] == other.labels[j]);
+        pair_up(&|i, j| self.transforms[i].get_name() == other.transforms[j].get_name());
+        pair_up(&|_, _| true);
+
+        let mut pairs: Vec<(Option<usize>, Option<usize>)> = partners.into_iter().enumerate().map(|(i, j)| (Some(i), j)).collect();
+        pairs.extend((0..other.len()).filter(|&j| !taken[j]).map(|j| (None, Some(j))));
+        pairs
+    }
+
+    /// Transition matrix of this IFS laid out over the transforms of a morph, given which of its transforms 
+    /// each one comes from. Transforms it does not have are never moved to and have an empty row. 
+    fn embedded_transitions(&self, indices: Vec<Option<usize>>) -> Transitions {
+        let matrix = self.transitions.clone()
+            .unwrap_or_else(|| Transitions::independent(self.len()))
+            .to_matrix(&self.weights());
+        Transitions::Matrix(indices.iter().map(|row| indices.iter().map(|column| match (row, column) {
+            (Some(i), Some(j)) => matrix[*i][*j],
+            _ => 0.0
+        }).collect()).collect())
+    }
+}
+
+impl IFS {
+    /// Weighted combination of several IFSes with matching transforms, with `coefficients` summing to one. 
+    /// 
+    /// This generalizes `morph` to more than two IFSes, e.g. for spline interpolation through keyframes. 
+    pub fn blend(ifs_list: &[&IFS], coefficients: &[f32]) -> IFS {
+        let first = ifs_list.first().expect("at least one IFS is needed");
+        if ifs_list.iter().any(|ifs| !first.check_transforms_match(ifs)) {
+            panic!("Transforms must match");
+        }
+
+        let transforms = (0..first.len()).map(|i| {
+            let transforms: Vec<Transform> = ifs_list.iter().map(|ifs| ifs.get_transform(i)).collect();
+            Transform::blend(&transforms, coefficients)
+        }).collect();
+        let mut out = IFS::from_transforms(transforms, None);
+        out.labels = first.labels.clone();
+
+        if ifs_list.iter().any(|ifs| ifs.transitions.is_some()) {
+            let independent = Transitions::independent(first.len());
+            let weights: Vec<Vec<f32>> = ifs_list.iter().map(|ifs| ifs.weights()).collect();
+            let transitions: Vec<(&Transitions, &[f32])> = ifs_list.iter().zip(weights.iter())
+                .map(|(ifs, w)| (ifs.transitions.as_ref().unwrap_or(&independent), w.as_slice()))
+                .collect();
+            out.set_transitions(Transitions::blend(&transitions, coefficients));
+        }
+        out
+    }
 }
 
 impl Default for IFS {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ifs::IFS;
+    use crate::transform::{AffineTransform, InverseJuliaTransform, Transformable};
+    use crate::transition::Transitions;
+
+    #[test]
+    fn test_pair_transforms() {
+        let a = IFS::from_transforms(vec![AffineTransform::random().into(), InverseJuliaTransform::random().into(),
+                                          AffineTransform::random().into()], None);
+        let mut b = IFS::from_transforms(vec![InverseJuliaTransform::random().into(), AffineTransform::random().into()], None);
+        assert_eq!(a.pair_transforms(&b), vec![(Some(0), Some(1)), (Some(1), Some(0)), (Some(2), None)]);
+
+        b.set_label(1, Some("stem".to_string()));
+        let mut a = a;
+        a.set_label(2, Some("stem".to_string()));
+        assert_eq!(a.pair_transforms(&b), vec![(Some(0), None), (Some(1), Some(0)), (Some(2), Some(1))]);
+        assert_eq!(b.pair_transforms(&a), vec![(Some(0), Some(1)), (Some(1), Some(2)), (None, Some(0))]);
+    }
+
+    #[test]
+    fn test_morph_different_transforms() {
+        let mut fern = IFS::barnsley_fern();
+        fern.set_transitions(Some(Transitions::random(fern.len())));
+        let triangle = IFS::sierpinski_triangle();
+
+        let start = fern.morph(&triangle, 0.0);
+        assert_eq!(start.len(), 4);
+        assert_eq!(start.weights(), fern.weights());
+
+        let end = fern.morph(&triangle, 1.0);
+        assert_eq!(end.get_transform(3).get_weight(), 0.0);
+        assert!(end.get_transitions().is_some());
+
+        let mut image = crate::image::Image::new(10, 10);
+        fern.morph(&IFS::from_transforms(vec![InverseJuliaTransform::random().into()], None), 0.5)
+            .evaluate(&mut image, 10, 10);
+    }
+
+    #[test]
+    fn test_points_out_of_view_are_dropped() {
+        let color = crate::util::Color{r: 1.0, g: 1.0, b: 1.0};
+        // every point lands on (-2.5, 0.0), left of the view, then on the center of the view
+        let outside = IFS::from_transforms(vec![AffineTransform::new(0.0, 0.0, 0.0, 0.0, -2.5, 0.0, color, 1.0).into()], None);
+        let inside = IFS::from_transforms(vec![AffineTransform::new(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, color, 1.0).into()], None);
+
+        let mut image = crate::image::Image::new(10, 10);
+        outside.evaluate(&mut image, 5, 4);
+        // these used to pile up in the first column
+        assert_eq!(image.total_hits(), 0);
+        inside.evaluate(&mut image, 5, 4);
+        assert_eq!(image.total_hits(), 20);
+    }
+}
@@ -1,7 +1,13 @@
 //! two-dimensional image representation
 
-use ndarray::Array3;
-use image::RgbImage;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use ndarray::{Array2, Array3, Axis};
+use image::{DynamicImage, ImageBuffer, Rgb, Rgb32FImage, RgbImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use crate::util::*;
 
 const RGB_LUMINANCE: [f32; 3] = [0.2126, 0.7152, 0.0722];
@@ -9,22 +15,185 @@ const DISPLAY_LUMINANCE_MAX: f32 = 200.0;
 const SCALEFACTOR_NUMERATOR: f32 = 5.828968; // 1.219 + (DISPLAY_LUMINANCE_MAX * 0.25).powf(0.4);
 const GAMMA_ENCODE: f32 = 0.45;
 
+/// 16-bit RGB image buffer
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// File formats an image can be saved in
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// tone mapped 8-bit color in whichever format the file extension names, like .png or .jpg
+    #[default]
+    EightBit,
+    /// tone mapped 16-bit PNG
+    Png16,
+    /// tone mapped 16-bit TIFF
+    Tiff16,
+    /// linear 32-bit float OpenEXR
+    Exr,
+    /// linear 32-bit float portable float map
+    Pfm,
+    /// the raw accumulation buffer, which can be merged with others later, see the `accumulation` module
+    Accumulation
+}
+
+impl OutputFormat {
+    /// Pick the format from the extension of `path`: .exr, .pfm, .acc, 16-bit for .tif and .tiff, 8-bit otherwise
+    pub fn from_path(path: &Path) -> OutputFormat {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "exr" => OutputFormat::Exr,
+            "pfm" => OutputFormat::Pfm,
+            "acc" => OutputFormat::Accumulation,
+            "tif" | "tiff" => OutputFormat::Tiff16,
+            _ => OutputFormat::EightBit
+        }
+    }
+
+    /// True for the formats that hold linear float values
+    pub fn is_float(&self) -> bool {
+        matches!(self, OutputFormat::Exr | OutputFormat::Pfm)
+    }
+}
+
+/// Which values go into float formats
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum HdrStage {
+    /// the accumulated color per sample, before any exposure is applied
+    #[default]
+    BeforeToneMapping,
+    /// scaled by the same exposure as the 8-bit image, but neither gamma encoded nor clipped
+    AfterToneMapping
+}
+
+/// What shows through where few or no points landed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Background {
+    /// a color blended in after tone mapping
+    Color(Color),
+    /// nothing, images get an alpha channel from the density of points
+    Transparent
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(BLACK)
+    }
+}
+
+const BLACK: Color = Color{r: 0.0, g: 0.0, b: 0.0};
+
 /// Two-dimensional image.
+/// 
+/// An image can also be a tile of a larger canvas, covering only the `x` in some range, see `Image::tile`. 
 pub struct Image {
-    data: Array3<f32>
+    data: Array3<f32>,
+    hits: Array2<u32>,
+    samples: u64,
+    x_offset: usize,
+    canvas_width: usize
 }
 
 impl Image {
     /// Create a new image with given `width` and `height`. 
     pub fn new(width: usize, height: usize) -> Image {
         Image {
-            data: Array3::zeros((width, height, 3))
+            data: Array3::zeros((width, height, 3)),
+            hits: Array2::zeros((width, height)),
+            samples: 0,
+            x_offset: 0,
+            canvas_width: width
+        }
+    }
+
+    /// Create the tile of a `canvas_width` by `height` image that covers the `x` in `xs`. 
+    /// 
+    /// Points are placed as on the whole canvas, and only the ones landing in the tile are kept. 
+    pub fn tile(canvas_width: usize, height: usize, xs: Range<usize>) -> Image {
+        assert!(xs.end <= canvas_width, "tile {:?} exceeds the canvas width {}", xs, canvas_width);
+        Image {
+            x_offset: xs.start,
+            canvas_width,
+            ..Image::new(xs.len(), height)
         }
     }
 
+    /// Create an image from accumulated color, per-pixel hit counts and the total number of samples drawn.
+    /// 
+    /// Panics if `data` and `hits` are not the same width and height. 
+    pub fn from_parts(data: Array3<f32>, hits: Array2<u32>, samples: u64) -> Image {
+        assert_eq!(data.shape()[..2], *hits.shape(), "color and hit count buffers differ in size");
+        let canvas_width = data.shape()[0];
+        Image { data, hits, samples, x_offset: 0, canvas_width }
+    }
+
     /// Blank out the image to all zeros.
     pub fn clear(&mut self) {
         self.data = Array3::zeros((self.width(), self.height(), 3));
+        self.hits = Array2::zeros((self.width(), self.height()));
+        self.samples = 0;
+    }
+
+    /// The accumulated color of every pixel, indexed by `[x, y, channel]`
+    pub fn data(&self) -> &Array3<f32> {
+        &self.data
+    }
+
+    /// How many samples landed in every pixel, indexed by `[x, y]`
+    pub fn hits(&self) -> &Array2<u32> {
+        &self.hits
+    }
+
+    /// Total number of samples drawn into the image, including the ones that fell outside of it
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Count `count` more samples as drawn, see `samples`
+    pub fn add_samples(&mut self, count: u64) {
+        self.samples += count;
+    }
+
+    /// Rough number of samples per pixel, the `iterations` to tone map with. Like `EvaluationSettings::samples_per_pixel` 
+    /// but from the samples actually drawn. 
+    pub fn samples_per_pixel(&self) -> usize {
+        let (width, height) = self.canvas_size();
+        1.max(self.samples as usize / (width * height))
+    }
+
+    /// Number of samples that landed in the image
+    pub fn total_hits(&self) -> u64 {
+        self.hits.iter().map(|&h| h as u64).sum()
+    }
+
+    /// Mean number of samples in the pixels hit at least once, zero if none were
+    pub fn mean_hits_per_lit_pixel(&self) -> f32 {
+        let lit = self.hits.iter().filter(|&&h| h > 0).count();
+        if lit == 0 {
+            return 0.0
+        }
+        self.hits.iter().map(|&h| h as f64).sum::<f64>() as f32 / lit as f32
+    }
+
+    /// How much the color per sample changed since the image held `previous` color from `previous_samples` samples, 
+    /// relative to its total. Zero when the image is black. 
+    pub fn relative_change(&self, previous: &Array3<f32>, previous_samples: u64) -> f32 {
+        let (now, before) = (self.samples.max(1) as f64, previous_samples.max(1) as f64);
+        let (mut change, mut total) = (0.0, 0.0);
+        for (&current, &old) in self.data.iter().zip(previous.iter()) {
+            change += (current as f64 / now - old as f64 / before).abs();
+            total += current as f64 / now;
+        }
+        if total == 0.0 { 0.0 } else { (change / total) as f32 }
+    }
+
+    /// Add the color, hit counts and samples of `other` to this image. 
+    /// 
+    /// Panics if the images are not the same size. 
+    pub fn merge(&mut self, other: &Image) {
+        assert_eq!(self.data.shape(), other.data.shape(), "only images of the same size can be merged");
+        self.data += &other.data;
+        self.hits += &other.hits;
+        self.samples += other.samples;
     }
 
     /// Get the width of the image. 
@@ -37,16 +206,34 @@ impl Image {
         self.data.shape()[1]
     }
 
-    /// At `(x, y)` add a bit of `radiance` color. 
+    /// Width and height of the whole canvas, which is the image itself unless it is a tile
+    pub fn canvas_size(&self) -> (usize, usize) {
+        (self.canvas_width, self.height())
+    }
+
+    /// At `(x, y)` of the canvas add a bit of `radiance` color. Points outside the image are dropped. 
     pub fn add_radiance(&mut self, x: usize, y: usize, radiance: Color) {
+        let x = x.wrapping_sub(self.x_offset);
         if x < self.width() && y < self.height() {
             self.data[[x, y, 0]] += radiance.r;
             self.data[[x, y, 1]] += radiance.g;
             self.data[[x, y, 2]] += radiance.b;
+            self.hits[[x, y]] += 1;
         }
     }
 
-    fn calculate_scalefactor(&self, iterations: usize) -> f32 {
+    /// Geometric mean of the luminance of all pixels, after dividing by `iterations`. 
+    /// 
+    /// This sets the exposure when tone mapping. Passing one value to the `_with_exposure` 
+    /// methods for several images, like the frames of an animation, gives them matching brightness. 
+    pub fn log_mean_luminance(&self, iterations: usize) -> f32 {
+        10.0f32.powf(self.log_luminance_sum(iterations) / (self.width() * self.height()) as f32)
+    }
+
+    /// Sum of the base 10 logarithms of the luminance of all pixels, after dividing by `iterations`. 
+    /// 
+    /// Adding these up over the tiles of a canvas gives the `log_mean_luminance` of the whole. 
+    pub fn log_luminance_sum(&self, iterations: usize) -> f32 {
         let mut sum_of_logs = 0.0;
 
         for x in 0..self.width() {
@@ -59,31 +246,167 @@ impl Image {
                 sum_of_logs += (lum.max(0.0001)).log10();
             }
         }
-        let log_mean_luminance = 10.0f32.powf(sum_of_logs / (self.width() * self.height()) as f32);
+        sum_of_logs
+    }
+
+    fn calculate_scalefactor(log_mean_luminance: f32) -> f32 {
         (SCALEFACTOR_NUMERATOR / (1.219 + log_mean_luminance.powf(0.4))).powf(2.5) / DISPLAY_LUMINANCE_MAX
     }
 
-    fn get_gamma_corrected_pixels(&self, iterations: usize) -> Array3<f32> {
-        let scalefactor = self.calculate_scalefactor(iterations);
+    fn get_gamma_corrected_pixels(&self, iterations: usize, log_mean_luminance: f32) -> Array3<f32> {
+        let scalefactor = Self::calculate_scalefactor(log_mean_luminance);
         (self.data.clone() * scalefactor / iterations as f32).mapv(|v| v.max(0.0).powf(GAMMA_ENCODE))
     }
 
     /// Save an image to the location `filename`. `iterations` is required to figure out the appropriate scaling for the IFS. 
     pub fn save(&self, filename: &str, iterations: usize) {
-        let pixels = self.get_gamma_corrected_pixels(iterations);
+        self.save_with_exposure(filename, iterations, self.log_mean_luminance(iterations));
+    }
 
-        let image = pixels.map(|v| ((v * 255.0 + 0.5) as u8).max(0).min(255));
-        let buffer = array_to_image(image); 
+    /// Save an image like `save`, but exposed for the given `log_mean_luminance` instead of the image's own. 
+    pub fn save_with_exposure(&self, filename: &str, iterations: usize, log_mean_luminance: f32) {
+        let buffer = self.to_rgb_image_with_exposure(iterations, log_mean_luminance);
         
         let _ = buffer.save(filename);
 
     }
 
+    /// Convert to an 8-bit RGB image buffer, scaled the same way as `save`. 
+    pub fn to_rgb_image(&self, iterations: usize) -> RgbImage {
+        self.to_rgb_image_with_exposure(iterations, self.log_mean_luminance(iterations))
+    }
+
+    /// Convert to an 8-bit RGB image buffer, exposed for the given `log_mean_luminance`. 
+    pub fn to_rgb_image_with_exposure(&self, iterations: usize, log_mean_luminance: f32) -> RgbImage {
+        array_to_image(self.to_u8_with_exposure(iterations, log_mean_luminance))
+    }
+
+    /// Convert to a 16-bit RGB image buffer, exposed for the given `log_mean_luminance`.
+    pub fn to_rgb16_image_with_exposure(&self, iterations: usize, log_mean_luminance: f32) -> Rgb16Image {
+        let pixels = self.get_gamma_corrected_pixels(iterations, log_mean_luminance)
+            .map(|v| (v * 65535.0 + 0.5).clamp(0.0, 65535.0) as u16);
+        let (height, width, _) = pixels.dim();
+        ImageBuffer::from_raw(width as u32, height as u32, pixels.into_raw_vec())
+            .expect("container should have the right size for the image dimensions")
+    }
+
+    /// Opacity of every pixel from the density of points in it, exposed like the colors for `log_mean_luminance`
+    pub fn alpha(&self, iterations: usize, log_mean_luminance: f32) -> Array2<f32> {
+        let scalefactor = Self::calculate_scalefactor(log_mean_luminance);
+        self.hits.mapv(|h| (h as f32 * scalefactor / iterations as f32).powf(GAMMA_ENCODE).min(1.0))
+    }
+
+    /// Tone mapped colors from 0 to 1 with `background` showing through where few points landed. 
+    /// 
+    /// A color background is blended in by the `alpha` of every pixel. A transparent one adds `alpha` as a fourth 
+    /// channel, with the colors divided by it so the image looks the same as before when composited over black. 
+    pub fn to_display(&self, iterations: usize, log_mean_luminance: f32, background: Background) -> Array3<f32> {
+        let pixels = self.get_gamma_corrected_pixels(iterations, log_mean_luminance).mapv(|v| v.min(1.0));
+        match background {
+            Background::Color(color) if color == BLACK => pixels,
+            Background::Color(color) => {
+                let alpha = self.alpha(iterations, log_mean_luminance);
+                let color = [color.r, color.g, color.b];
+                let mut pixels = pixels;
+                for ((x, y, channel), value) in pixels.indexed_iter_mut() {
+                    *value = (*value + (1.0 - alpha[[x, y]]) * color[channel]).min(1.0);
+                }
+                pixels
+            },
+            Background::Transparent => {
+                let alpha = self.alpha(iterations, log_mean_luminance);
+                Array3::from_shape_fn((self.width(), self.height(), 4), |(x, y, channel)| {
+                    let a = alpha[[x, y]];
+                    match channel {
+                        3 => a,
+                        _ if a > 0.0 => (pixels[[x, y, channel]] / a).min(1.0),
+                        _ => 0.0
+                    }
+                })
+            }
+        }
+    }
+
+    /// Tone map into an 8 or 16-bit image with `background` showing through, RGBA if it is transparent
+    pub fn to_display_image(&self, iterations: usize, log_mean_luminance: f32, sixteen_bit: bool, 
+                            background: Background) -> DynamicImage {
+        let pixels = self.to_display(iterations, log_mean_luminance, background);
+        let (height, width, channels) = pixels.dim();
+        let (width, height) = (width as u32, height as u32);
+        let expect = "container should have the right size for the image dimensions";
+        if sixteen_bit {
+            let raw = pixels.mapv(|v| (v * 65535.0 + 0.5).clamp(0.0, 65535.0) as u16).into_raw_vec();
+            match channels {
+                4 => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, raw).expect(expect)),
+                _ => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, raw).expect(expect))
+            }
+        } else {
+            let raw = pixels.mapv(|v| (v * 255.0 + 0.5).clamp(0.0, 255.0) as u8).into_raw_vec();
+            match channels {
+                4 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw).expect(expect)),
+                _ => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw).expect(expect))
+            }
+        }
+    }
+
+    /// Linear float colors, divided by `iterations` and, after tone mapping, scaled by the exposure for `log_mean_luminance`
+    pub fn to_linear(&self, iterations: usize, stage: HdrStage, log_mean_luminance: f32) -> Array3<f32> {
+        let scalefactor = match stage {
+            HdrStage::BeforeToneMapping => 1.0,
+            HdrStage::AfterToneMapping => Self::calculate_scalefactor(log_mean_luminance)
+        };
+        self.data.clone() * scalefactor / iterations as f32
+    }
+
+    /// Save in `format`. 8-bit and 16-bit formats are tone mapped as in `save` with `background` showing through,
+    /// float formats hold linear values from before or after tone mapping depending on `stage`.
+    pub fn save_as(&self, filename: &str, iterations: usize, format: OutputFormat, stage: HdrStage, 
+                   background: Background) -> Result<(), String> {
+        let log_mean_luminance = self.log_mean_luminance(iterations);
+        match format {
+            OutputFormat::EightBit => self.to_display_image(iterations, log_mean_luminance, false, background)
+                .save(filename).map_err(|e| e.to_string()),
+            OutputFormat::Png16 => self.to_display_image(iterations, log_mean_luminance, true, background)
+                .save_with_format(filename, image::ImageFormat::Png).map_err(|e| e.to_string()),
+            OutputFormat::Tiff16 => self.to_display_image(iterations, log_mean_luminance, true, background)
+                .save_with_format(filename, image::ImageFormat::Tiff).map_err(|e| e.to_string()),
+            OutputFormat::Exr => {
+                let pixels = self.to_linear(iterations, stage, log_mean_luminance);
+                let (height, width, _) = pixels.dim();
+                let buffer: Rgb32FImage = ImageBuffer::from_raw(width as u32, height as u32, pixels.into_raw_vec())
+                    .expect("container should have the right size for the image dimensions");
+                buffer.save_with_format(filename, image::ImageFormat::OpenExr).map_err(|e| e.to_string())
+            },
+            OutputFormat::Pfm => write_pfm(filename, &self.to_linear(iterations, stage, log_mean_luminance))
+                .map_err(|e| e.to_string()),
+            OutputFormat::Accumulation => self.save_accumulation(Path::new(filename))
+        }
+    }
+
     /// Convert the `f32` Array of colors to a `u8` scaled image. 
     pub fn to_u8(&self, iterations: usize) -> Array3<u8> {
-        self.get_gamma_corrected_pixels(iterations)
-            .map(|v| ((v * 255.0 + 0.5) as u8).max(0).min(255))
+        self.to_u8_with_exposure(iterations, self.log_mean_luminance(iterations))
+    }
+
+    /// Convert the `f32` Array of colors to a `u8` scaled image, exposed for the given `log_mean_luminance`. 
+    pub fn to_u8_with_exposure(&self, iterations: usize, log_mean_luminance: f32) -> Array3<u8> {
+        self.get_gamma_corrected_pixels(iterations, log_mean_luminance)
+            .map(|v| (v * 255.0 + 0.5).clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// Write a color portable float map, which stores little-endian rows from the bottom up
+fn write_pfm(filename: &str, pixels: &Array3<f32>) -> std::io::Result<()> {
+    let (height, width, _) = pixels.dim();
+    let mut file = BufWriter::new(File::create(filename)?);
+    // a negative scale marks the data as little-endian
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.axis_iter(Axis(0)).rev() {
+        for value in row.iter() {
+            file.write_all(&value.to_le_bytes())?;
+        }
     }
+    file.flush()
 }
 
 fn array_to_image(arr: Array3<u8>) -> RgbImage {
@@ -95,3 +418,80 @@ fn array_to_image(arr: Array3<u8>) -> RgbImage {
     RgbImage::from_raw(width as u32, height as u32, raw)
         .expect("container should have the right size for the image dimensions")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::image::{Background, HdrStage, Image, OutputFormat};
+    use crate::util::{test_path, Color};
+
+    fn test_image() -> Image {
+        let mut image = Image::new(4, 3);
+        image.add_radiance(1, 2, Color{r: 2.0, g: 1.0, b: 0.5});
+        image.add_radiance(3, 0, Color{r: 0.5, g: 0.5, b: 0.5});
+        image
+    }
+
+    #[test]
+    fn test_output_format_from_path() {
+        assert_eq!(OutputFormat::from_path(Path::new("a.png")), OutputFormat::EightBit);
+        assert_eq!(OutputFormat::from_path(Path::new("a.TIF")), OutputFormat::Tiff16);
+        assert_eq!(OutputFormat::from_path(Path::new("a.exr")), OutputFormat::Exr);
+        assert_eq!(OutputFormat::from_path(Path::new("a.pfm")), OutputFormat::Pfm);
+        assert_eq!(OutputFormat::from_path(Path::new("a.acc")), OutputFormat::Accumulation);
+    }
+
+    #[test]
+    fn test_save_high_bit_depth() {
+        let image = test_image();
+        for (name, format) in [("png", OutputFormat::Png16), ("tif", OutputFormat::Tiff16), ("exr", OutputFormat::Exr)] {
+            let path = test_path(&format!("save_high_bit_depth.{}", name));
+            image.save_as(path.to_str().unwrap(), 1, format, HdrStage::BeforeToneMapping, Background::default()).unwrap();
+            let loaded = ::image::open(&path).unwrap();
+            assert_eq!((loaded.width(), loaded.height()), (3, 4));
+            assert!(!matches!(loaded.color(), ::image::ColorType::Rgb8));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_save_pfm() {
+        let path = test_path("save_pfm.pfm");
+        test_image().save_as(path.to_str().unwrap(), 2, OutputFormat::Pfm, HdrStage::BeforeToneMapping, Background::default())
+            .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let header = b"PF\n3 4\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 3 * 4 * 3 * 4);
+
+        // rows are stored bottom up, so row 1 of the image is the third in the file
+        let row = &bytes[header.len() + 2 * 3 * 3 * 4..];
+        let red = f32::from_le_bytes(row[6 * 4..7 * 4].try_into().unwrap());
+        assert_eq!(red, 1.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background() {
+        let image = test_image();
+        let log_mean_luminance = image.log_mean_luminance(1);
+        let alpha = image.alpha(1, log_mean_luminance);
+        assert_eq!(alpha[[0, 0]], 0.0);
+        assert!(alpha[[1, 2]] > 0.0);
+
+        // a background shows through completely where nothing landed
+        let white = Background::Color(Color{r: 1.0, g: 1.0, b: 1.0});
+        let over_white = image.to_display(1, log_mean_luminance, white);
+        assert_eq!(over_white[[0, 0, 1]], 1.0);
+
+        let transparent = image.to_display(1, log_mean_luminance, Background::Transparent);
+        assert_eq!(transparent.dim(), (4, 3, 4));
+        assert_eq!(transparent[[0, 0, 3]], 0.0);
+        // over black, the straight colors times alpha give back the plain image
+        let plain = image.to_display(1, log_mean_luminance, Background::default());
+        let a = transparent[[1, 2, 3]];
+        assert!((transparent[[1, 2, 2]] * a - plain[[1, 2, 2]]).abs() < 1e-5);
+        assert!(matches!(image.to_display_image(1, log_mean_luminance, false, Background::Transparent),
+                         ::image::DynamicImage::ImageRgba8(_)));
+    }
+}
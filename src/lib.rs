@@ -26,10 +26,24 @@
 //! # Defining iterated function systems in files
 //! There are two kinds of files that are used to define a IFS:
 //! 1. *templates*: These are toml files specify which transforms you want to run but not their parameters. 
-//!     Each time you run one you will get a different result. They're good for generating many different images. 
+//!    Each time you run one you will get a different result. They're good for generating many different images. 
 //! 2. *configs*: These are json files that fully specify the transforms and their parameters. You can use them 
-//!     to regenerate an image at a higher resolution, change the color scheme, or explore how changing parameters impacts 
-//!     the IFS. 
+//!    to regenerate an image at a higher resolution, change the color scheme, or explore how changing parameters impacts 
+//!    the IFS. 
+//! 
+//! `Config::validate` and `Template::validate` check a file for problems without running it, 
+//! see the [`validate`] module. 
+//! 
+//! JSON Schemas for both kinds of files are available from the [`schema`] module or with `barnsley schema`. 
+//! 
+//! Classic systems like Barnsley's fern are available ready-made in the [`presets`] module. 
+//! 
+//! Both can optionally make the IFS *recurrent*, where the next transform is chosen based on the previous one. 
+//! See the [`transition`] module. 
+//! 
+//! Renders saved as raw `.acc` accumulation buffers can be merged and tone mapped later, see the [`accumulation`] module. 
+//! 
+//! The raw points of the chaos game can be written out as a point cloud instead of an image, see the [`pointcloud`] module. 
 //! 
 pub mod ifs;
 pub mod image;
@@ -38,3 +52,15 @@ pub mod util;
 pub mod config;
 pub mod template;
 pub mod animation;
+pub mod easing;
+pub mod camera;
+pub mod transition;
+pub mod presets;
+pub mod schema;
+pub mod validate;
+pub mod encode;
+pub mod metadata;
+pub mod accumulation;
+pub mod checkpoint;
+pub mod tiles;
+pub mod pointcloud;
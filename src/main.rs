@@ -1,10 +1,17 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use barnsley::animation::AnimationConfig;
 use barnsley::config::*;
+use barnsley::image::{Background, HdrStage, Image, OutputFormat};
 use barnsley::template::*;
-use clap::{Parser, Subcommand};
-use std::fs::File;
+use barnsley::presets::Preset;
+use barnsley::schema::*;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
 
 
 
@@ -21,9 +28,65 @@ enum Commands {
     /// Generates a config from a template
     Generate { template_path: String },
     /// Evaluates a config file
-    Evaluate { config_path: String},
+    Evaluate {
+        config_path: String,
+        /// continue from the last checkpoint of an interrupted run, see checkpoint_every
+        #[arg(long)]
+        resume: bool
+    },
     /// Generates a config from a template and evaluates it, combo of generate and evaluate
-    Construct { template_path: String}
+    Construct { template_path: String},
+    /// Renders the frames of an animation file
+    Animate {
+        animation_path: String,
+        /// skip frames that already exist in the output directory
+        #[arg(long)]
+        resume: bool,
+        /// number of frames rendered at the same time, one per core by default
+        #[arg(long)]
+        workers: Option<usize>
+    },
+    /// Prints the config of a classic IFS, e.g. barnsley-fern. Run without a name to list them
+    Preset { name: Option<String> },
+    /// Prints an old config (.json) or template (.toml) in the current file format
+    Migrate {
+        path: String,
+        /// where to write the migrated file instead of printing it, must not be the file being migrated
+        #[arg(short, long)]
+        output: Option<String>
+    },
+    /// Checks a config (.json) or template (.toml) for problems without running it. Animation files are not covered
+    Validate { path: String },
+    /// Prints the config stored in a PNG rendered by evaluate, so it can be rendered again
+    Extract { path: String },
+    /// Writes the points of a config's chaos game to a .csv, .ply or raw f32 .bin file instead of rendering it
+    Points {
+        config_path: String,
+        /// where to write the points, its extension picks the format
+        #[arg(short, long)]
+        output: String
+    },
+    /// Sums accumulation buffers (.acc) rendered by evaluate and saves them as one image
+    Merge {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// where to save the merged image, its extension picks the format
+        #[arg(short, long)]
+        output: String
+    },
+    /// Prints the JSON Schema for a config (the default), template, animation, or transform
+    Schema {
+        #[arg(value_enum, default_value_t = SchemaKind::Config)]
+        kind: SchemaKind
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum SchemaKind {
+    Config,
+    Template,
+    Animation,
+    Transform
 }
 
 fn load_template(template_path: &String) -> Template{
@@ -41,6 +104,13 @@ fn load_config(config_path: &String) -> Config {
 }
 
 
+fn load_animation(animation_path: &String) -> AnimationConfig {
+    let mut file = File::open(animation_path).unwrap();
+    let mut data = String::new();
+    file.read_to_string(&mut data).unwrap();
+    serde_json::from_str(&data).unwrap()
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -51,14 +121,128 @@ fn main() {
             let config = load_template(template_path).generate();
             println!("{}", serde_json::to_string(&config).unwrap());
         },
-        Commands::Evaluate { config_path } => {
+        Commands::Evaluate { config_path, resume } => {
             let config = load_config(config_path);
-            config.run();
+            config.run_with_resume(*resume);
         },
         Commands::Construct { template_path } => {
             let config = load_template(template_path).generate();
             println!("{}", serde_json::to_string(&config).unwrap());        
             config.run();
+        },
+        Commands::Animate { animation_path, resume, workers } => {
+            let animation = load_animation(animation_path);
+            let base_directory = Path::new(animation_path).parent().unwrap_or(Path::new(""));
+            let workers = workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            animation.run(base_directory, *resume, workers);
+        },
+        Commands::Preset { name } => {
+            match name.as_deref().map(Preset::from_str) {
+                Some(Ok(preset)) => println!("{}", serde_json::to_string(&preset.config()).unwrap()),
+                Some(Err(_)) => {
+                    eprintln!("unknown preset {}, choose one of:", name.as_deref().unwrap());
+                    for preset in Preset::iter() {
+                        eprintln!("{}", preset);
+                    }
+                    std::process::exit(1);
+                },
+                None => {
+                    for preset in Preset::iter() {
+                        println!("{}", preset);
+                    }
+                }
+            }
+        },
+        Commands::Migrate { path, output } => {
+            let migrated = if path.ends_with(".toml") {
+                toml::to_string(&load_template(path).migrate()).unwrap()
+            } else {
+                serde_json::to_string_pretty(&load_config(path).migrate()).unwrap()
+            };
+            match output {
+                Some(output) if fs::canonicalize(output).ok() == fs::canonicalize(path).ok() => {
+                    eprintln!("refusing to overwrite {}, write the migrated file somewhere else", path);
+                    std::process::exit(1);
+                },
+                Some(output) => fs::write(output, migrated).unwrap(),
+                None => print!("{}", migrated)
+            }
+        },
+        Commands::Validate { path } => {
+            let mut data = String::new();
+            File::open(path).unwrap().read_to_string(&mut data).unwrap();
+            let is_animation = serde_json::from_str::<serde_json::Value>(&data).ok()
+                .is_some_and(|value| value.get("keyframes").is_some());
+            if is_animation {
+                eprintln!("{} is an animation file, validate only checks configs and templates", path);
+                std::process::exit(1);
+            }
+            let problems = if path.ends_with(".toml") {
+                toml::from_str::<Template>(&data).map(|t| t.validate()).map_err(|e| e.to_string())
+            } else {
+                serde_json::from_str::<Config>(&data).map(|c| c.validate()).map_err(|e| e.to_string())
+            };
+            match problems {
+                Ok(problems) if problems.is_empty() => println!("{} is valid", path),
+                Ok(problems) => {
+                    for problem in problems {
+                        println!("{}", problem);
+                    }
+                    std::process::exit(1);
+                },
+                Err(error) => {
+                    println!("{} could not be parsed: {}", path, error);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Extract { path } => {
+            match Config::extract(Path::new(path)) {
+                Ok(config) => println!("{}", serde_json::to_string_pretty(&config).unwrap()),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Points { config_path, output } => {
+            if let Err(error) = load_config(config_path).export_points(Path::new(output)) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        },
+        Commands::Merge { paths, output } => {
+            let mut merged: Option<Image> = None;
+            for path in paths {
+                let image = Image::load_accumulation(Path::new(path)).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+                match merged.as_mut() {
+                    Some(merged) if merged.width() != image.width() || merged.height() != image.height() => {
+                        eprintln!("{} is {}x{} but the first buffer is {}x{}", path, image.width(), image.height(),
+                                  merged.width(), merged.height());
+                        std::process::exit(1);
+                    },
+                    Some(merged) => merged.merge(&image),
+                    None => merged = Some(image)
+                }
+            }
+            let merged = merged.unwrap();
+            let format = OutputFormat::from_path(Path::new(output));
+            if let Err(error) = merged.save_as(output, merged.samples_per_pixel(), format, HdrStage::default(), Background::default()) {
+                eprintln!("could not save {}: {}", output, error);
+                std::process::exit(1);
+            }
+        },
+        Commands::Schema { kind } => {
+            let schema = match kind {
+                SchemaKind::Config => config_schema(),
+                SchemaKind::Template => template_schema(),
+                SchemaKind::Animation => animation_schema(),
+                SchemaKind::Transform => transform_schema()
+            };
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap());
         }
     }
 }
@@ -0,0 +1,140 @@
+//! keeping the config that made an image inside the image
+//!
+//! `Config::run` writes PNGs with the full config, the seed the points were drawn with and the
+//! version of the crate stored in text chunks, so the image can be rendered again later:
+//!
+//! ```sh
+//! barnsley extract fern.png > fern.json
+//! barnsley evaluate fern.json
+//! ```
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::borrow::Cow;
+use image::DynamicImage;
+use crate::config::Config;
+
+/// Keyword of the iTXt chunk holding the serialized config
+pub const CONFIG_KEYWORD: &str = "barnsley-config";
+/// Keyword of the tEXt chunk holding the seed the image was rendered with
+pub const SEED_KEYWORD: &str = "barnsley-seed";
+/// Standard keyword of the tEXt chunk naming the program, and version, that made the image
+pub const SOFTWARE_KEYWORD: &str = "Software";
+
+/// Text chunks describing an image rendered from `config` with `seed`
+pub fn config_text(config: &Config, seed: u64) -> Vec<(String, String)> {
+    vec![
+        (CONFIG_KEYWORD.to_string(), serde_json::to_string(config).unwrap()),
+        (SEED_KEYWORD.to_string(), seed.to_string()),
+        (SOFTWARE_KEYWORD.to_string(), format!("barnsley {}", env!("CARGO_PKG_VERSION"))),
+    ]
+}
+
+/// Write an 8 or 16-bit RGB or RGBA PNG with `text` as (keyword, text) chunks, iTXt for the config and tEXt otherwise
+pub fn save_png_with_text(path: &Path, image: &DynamicImage, text: &[(String, String)]) -> Result<(), String> {
+    let (color, depth) = png_color(image)?;
+    let mut writer = png_writer(path, image.width(), image.height(), color, depth, text)?;
+    writer.write_image_data(&png_bytes(image)).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())
+}
+
+/// PNG color type and bit depth of `image`, which must be 8 or 16-bit RGB or RGBA
+pub fn png_color(image: &DynamicImage) -> Result<(png::ColorType, png::BitDepth), String> {
+    match image {
+        DynamicImage::ImageRgb8(_) => Ok((png::ColorType::Rgb, png::BitDepth::Eight)),
+        DynamicImage::ImageRgba8(_) => Ok((png::ColorType::Rgba, png::BitDepth::Eight)),
+        DynamicImage::ImageRgb16(_) => Ok((png::ColorType::Rgb, png::BitDepth::Sixteen)),
+        DynamicImage::ImageRgba16(_) => Ok((png::ColorType::Rgba, png::BitDepth::Sixteen)),
+        other => Err(format!("{:?} images can not be written with text", other.color()))
+    }
+}
+
+/// The samples of `image` as PNG stores them, 16-bit samples big-endian
+pub fn png_bytes(image: &DynamicImage) -> Cow<'_, [u8]> {
+    match image {
+        DynamicImage::ImageRgb16(buffer) => Cow::Owned(buffer.as_raw().iter().flat_map(|v| v.to_be_bytes()).collect()),
+        DynamicImage::ImageRgba16(buffer) => Cow::Owned(buffer.as_raw().iter().flat_map(|v| v.to_be_bytes()).collect()),
+        other => Cow::Borrowed(other.as_bytes())
+    }
+}
+
+/// Write a PNG with `text` chunks like `save_png_with_text`, without holding the whole image in memory.
+///
+/// `write_rows` is handed the stream to write the rows of samples to, from the top, 16-bit samples big-endian.
+pub fn stream_png_with_text<F>(path: &Path, width: u32, height: u32, color: png::ColorType, depth: png::BitDepth,
+                               text: &[(String, String)], write_rows: F) -> Result<(), String>
+where F: FnOnce(&mut dyn Write) -> Result<(), String> {
+    let mut writer = png_writer(path, width, height, color, depth, text)?;
+    let mut stream = writer.stream_writer().map_err(|e| e.to_string())?;
+    write_rows(&mut stream)?;
+    stream.finish().map_err(|e| e.to_string())
+}
+
+fn png_writer(path: &Path, width: u32, height: u32, color: png::ColorType, depth: png::BitDepth,
+              text: &[(String, String)]) -> Result<png::Writer<BufWriter<File>>, String> {
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    for (keyword, value) in text {
+        let added = if keyword == CONFIG_KEYWORD {
+            // configs can be long and may hold non latin-1 characters in paths
+            encoder.add_itxt_chunk(keyword.clone(), value.clone())
+        } else {
+            encoder.add_text_chunk(keyword.clone(), value.clone())
+        };
+        added.map_err(|e| e.to_string())?;
+    }
+    encoder.write_header().map_err(|e| e.to_string())
+}
+
+/// Read every text chunk of the PNG at `path` as (keyword, text) pairs
+pub fn read_png_text(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let file = File::open(path).map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+    let reader = png::Decoder::new(BufReader::new(file)).read_info()
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let info = reader.info();
+
+    let mut text: Vec<(String, String)> = info.uncompressed_latin1_text.iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect();
+    for chunk in &info.compressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().map_err(|e| e.to_string())?));
+    }
+    for chunk in &info.utf8_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().map_err(|e| e.to_string())?));
+    }
+    Ok(text)
+}
+
+impl Config {
+    /// Read back the config stored in a PNG written by `Config::run`
+    pub fn extract(path: &Path) -> Result<Config, String> {
+        let text = read_png_text(path)?;
+        let (_, json) = text.iter().find(|(keyword, _)| keyword == CONFIG_KEYWORD)
+            .ok_or_else(|| format!("{} has no barnsley config in it", path.display()))?;
+        serde_json::from_str(json).map_err(|e| format!("could not parse the config in {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{test_config, Config};
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = test_config("config_round_trip");
+        let path = std::path::PathBuf::from(&config.image_settings.path);
+        config.run();
+        let rendered = std::fs::read(&path).unwrap();
+
+        let extracted = Config::extract(&path).unwrap();
+        assert_eq!(extracted.transforms.len(), 3);
+        assert!(extracted.evaluation_settings.seed.is_some());
+
+        // the extracted config, seed included, renders the same image again
+        extracted.run();
+        assert_eq!(std::fs::read(&path).unwrap(), rendered);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! exporting the raw points of the chaos game instead of an image
+//!
+//! `barnsley points fern.json -o fern.ply` runs the config's `num_points` trajectories of `num_iterations`
+//! iterations, with its seed if it has one, and writes every point reached, in the coordinates of the IFS,
+//! with its color and the index of the transform that led to it. The extension of the output picks the format:
+//!
+//! * `.csv`: a header line `x,y,r,g,b,transform` and one line per point
+//! * `.ply`: binary little-endian PLY with float `x`, `y` and `z` (always 0), uchar `red`, `green` and `blue`
+//!   and an int `transform` per vertex, which most point-cloud and mesh tools open
+//! * `.bin` or `.f32`: headerless little-endian `f32`s, six per point: x, y, r, g, b and the transform
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use rand::{rngs::StdRng, SeedableRng};
+use crate::config::Config;
+use crate::ifs::PointSink;
+use crate::util::Color;
+
+/// File formats points can be written in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PointFormat {
+    /// comma separated text with a header line
+    Csv,
+    /// binary PLY
+    Ply,
+    /// raw little-endian `f32`s
+    Binary
+}
+
+impl PointFormat {
+    /// Pick the format from the extension of `path`, if it names one
+    pub fn from_path(path: &Path) -> Option<PointFormat> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(PointFormat::Csv),
+            "ply" => Some(PointFormat::Ply),
+            "bin" | "f32" => Some(PointFormat::Binary),
+            _ => None
+        }
+    }
+}
+
+/// Writes the points it is handed to a file as they come
+pub struct PointWriter {
+    file: BufWriter<File>,
+    format: PointFormat,
+    remaining: usize,
+    error: Option<io::Error>
+}
+
+impl PointWriter {
+    /// Create `path` to write `count` points to in `format`. The PLY header needs the count up front.
+    pub fn create(path: &Path, format: PointFormat, count: usize) -> Result<PointWriter, String> {
+        let file = File::create(path).map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        let mut writer = PointWriter { file: BufWriter::new(file), format, remaining: count, error: None };
+        let header = match format {
+            PointFormat::Csv => "x,y,r,g,b,transform\n".to_string(),
+            PointFormat::Ply => format!("ply\nformat binary_little_endian 1.0\ncomment written by barnsley {}\n\
+                element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+                property uchar red\nproperty uchar green\nproperty uchar blue\nproperty int transform\nend_header\n",
+                env!("CARGO_PKG_VERSION"), count),
+            PointFormat::Binary => String::new()
+        };
+        writer.file.write_all(header.as_bytes()).map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        Ok(writer)
+    }
+
+    /// Flush the file, reporting the first error writing any point hit
+    pub fn finish(mut self) -> Result<(), String> {
+        if let Some(error) = self.error.take() {
+            return Err(error.to_string())
+        }
+        if self.remaining != 0 {
+            return Err(format!("{} fewer points were written than announced", self.remaining))
+        }
+        self.file.flush().map_err(|e| e.to_string())
+    }
+
+    fn write_point(&mut self, x: f32, y: f32, color: Color, transform: usize) -> io::Result<()> {
+        match self.format {
+            PointFormat::Csv => writeln!(self.file, "{},{},{},{},{},{}", x, y, color.r, color.g, color.b, transform),
+            PointFormat::Ply => {
+                for value in [x, y, 0.0] {
+                    self.file.write_all(&value.to_le_bytes())?;
+                }
+                let channels = [color.r, color.g, color.b].map(|c| (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8);
+                self.file.write_all(&channels)?;
+                self.file.write_all(&(transform as i32).to_le_bytes())
+            },
+            PointFormat::Binary => {
+                for value in [x, y, color.r, color.g, color.b, transform as f32] {
+                    self.file.write_all(&value.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl PointSink for PointWriter {
+    fn add_point(&mut self, x: f32, y: f32, color: Color, transform: usize) {
+        if self.error.is_some() || self.remaining == 0 {
+            return
+        }
+        self.remaining -= 1;
+        if let Err(error) = self.write_point(x, y, color, transform) {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl Config {
+    /// Write the points of this config's chaos game to `path` instead of rendering an image, see the module documentation
+    pub fn export_points(&self, path: &Path) -> Result<(), String> {
+        let format = PointFormat::from_path(path)
+            .ok_or_else(|| format!("{} should end in .csv, .ply, .bin or .f32", path.display()))?;
+        let num_points = self.evaluation_settings.num_points as usize;
+        let num_iterations = self.evaluation_settings.num_iterations as usize;
+        let seed = self.evaluation_settings.seed.unwrap_or_else(rand::random);
+
+        let mut writer = PointWriter::create(path, format, num_points * num_iterations)?;
+        self.ifs().evaluate_into(&mut writer, num_points, num_iterations, &mut StdRng::seed_from_u64(seed));
+        writer.finish().map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::presets::Preset;
+    use crate::util::test_path;
+
+    #[test]
+    fn test_export_points() {
+        let mut config = Preset::SierpinskiTriangle.config();
+        config.evaluation_settings.num_points = 3;
+        config.evaluation_settings.num_iterations = 5;
+
+        let csv = test_path("points.csv");
+        config.export_points(&csv).unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "x,y,r,g,b,transform");
+        assert_eq!(lines.len(), 1 + 15);
+        assert!(lines[1..].iter().all(|line| line.split(',').count() == 6));
+
+        let ply = test_path("points.ply");
+        config.export_points(&ply).unwrap();
+        let bytes = std::fs::read(&ply).unwrap();
+        let header_end = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        assert!(String::from_utf8_lossy(&bytes[..header_end]).contains("element vertex 15\n"));
+        assert_eq!(bytes.len() - header_end, 15 * (3 * 4 + 3 + 4));
+
+        let binary = test_path("points.f32");
+        config.export_points(&binary).unwrap();
+        assert_eq!(std::fs::metadata(&binary).unwrap().len(), 15 * 6 * 4);
+        for path in [csv, ply, binary] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert!(config.export_points(&test_path("points.txt")).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! library of classic iterated function systems
+//!
+//! Each preset is stored with its classic published coefficients, where a map sends `(x, y)` to
+//! `(a x + b y + e, c x + d y + f)` and is chosen with probability `p`. Those coefficients live in
+//! the usual mathematical frame with `y` pointing up, so before use they are conjugated into the
+//! frame barnsley renders in: the attractor is centered, scaled to fill the image, and turned so that
+//! it appears upright.
+//!
+//! ```rust
+//! use barnsley::{ifs::IFS, image::Image, presets::Preset};
+//!
+//! let fern = IFS::barnsley_fern();
+//! let dragon = IFS::from_preset(Preset::HeighwayDragon);
+//! let mut image = Image::new(100, 100);
+//! fern.evaluate(&mut image, 10, 100);
+//! ```
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use strum_macros::{Display, EnumIter, EnumString};
+use crate::config::{Config, EvaluationSettings, ImageSettings, SCHEMA_VERSION};
+use crate::ifs::IFS;
+use crate::image::HdrStage;
+use crate::transform::{AffineTransform, Transform};
+use crate::util::Color;
+
+/// Classic map coefficients `[a, b, c, d, e, f, p]`
+type Coefficients = [f32; 7];
+
+/// Half-width of the region a preset is scaled to fill, leaving a small border inside the visible `[-2, 2]`
+const VIEW_HALF_WIDTH: f32 = 1.8;
+
+const SQRT_3: f32 = 1.732_050_8;
+
+/// Colors handed out to the maps of a preset, in order
+const PALETTE: [Color; 8] = [
+    Color{r: 0.10, g: 0.65, b: 0.20},
+    Color{r: 0.95, g: 0.45, b: 0.10},
+    Color{r: 0.20, g: 0.45, b: 0.95},
+    Color{r: 0.90, g: 0.15, b: 0.35},
+    Color{r: 0.95, g: 0.85, b: 0.20},
+    Color{r: 0.55, g: 0.25, b: 0.85},
+    Color{r: 0.15, g: 0.80, b: 0.80},
+    Color{r: 0.85, g: 0.85, b: 0.85},
+];
+
+const BARNSLEY_FERN: [Coefficients; 4] = [
+    [0.0, 0.0, 0.0, 0.16, 0.0, 0.0, 0.01],
+    [0.85, 0.04, -0.04, 0.85, 0.0, 1.6, 0.85],
+    [0.2, -0.26, 0.23, 0.22, 0.0, 1.6, 0.07],
+    [-0.15, 0.28, 0.26, 0.24, 0.0, 0.44, 0.07],
+];
+
+const SIERPINSKI_TRIANGLE: [Coefficients; 3] = [
+    [0.5, 0.0, 0.0, 0.5, 0.0, 0.0, 1.0],
+    [0.5, 0.0, 0.0, 0.5, 0.5, 0.0, 1.0],
+    [0.5, 0.0, 0.0, 0.5, 0.25, SQRT_3 / 4.0, 1.0],
+];
+
+const THIRD: f32 = 1.0 / 3.0;
+
+const SIERPINSKI_CARPET: [Coefficients; 8] = [
+    [THIRD, 0.0, 0.0, THIRD, 0.0, 0.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, THIRD, 0.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, 0.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 0.0, THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 0.0, 2.0 * THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, THIRD, 2.0 * THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, 2.0 * THIRD, 1.0],
+];
+
+const VICSEK: [Coefficients; 5] = [
+    [THIRD, 0.0, 0.0, THIRD, THIRD, THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 0.0, 0.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, 0.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 0.0, 2.0 * THIRD, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, 2.0 * THIRD, 1.0],
+];
+
+const HEIGHWAY_DRAGON: [Coefficients; 2] = [
+    [0.5, -0.5, 0.5, 0.5, 0.0, 0.0, 1.0],
+    [-0.5, -0.5, 0.5, -0.5, 1.0, 0.0, 1.0],
+];
+
+const KOCH_CURVE: [Coefficients; 4] = [
+    [THIRD, 0.0, 0.0, THIRD, 0.0, 0.0, 1.0],
+    [1.0 / 6.0, -SQRT_3 / 6.0, SQRT_3 / 6.0, 1.0 / 6.0, THIRD, 0.0, 1.0],
+    [1.0 / 6.0, SQRT_3 / 6.0, -SQRT_3 / 6.0, 1.0 / 6.0, 0.5, SQRT_3 / 6.0, 1.0],
+    [THIRD, 0.0, 0.0, THIRD, 2.0 * THIRD, 0.0, 1.0],
+];
+
+const LEVY_C_CURVE: [Coefficients; 2] = [
+    [0.5, -0.5, 0.5, 0.5, 0.0, 0.0, 1.0],
+    [0.5, 0.5, -0.5, 0.5, 0.5, 0.5, 1.0],
+];
+
+const MAPLE_LEAF: [Coefficients; 4] = [
+    [0.14, 0.01, 0.0, 0.51, -0.08, -1.31, 0.10],
+    [0.43, 0.52, -0.45, 0.5, 1.49, -0.75, 0.35],
+    [0.45, -0.49, 0.47, 0.47, -1.62, -0.74, 0.35],
+    [0.49, 0.0, 0.0, 0.51, 0.02, 1.62, 0.20],
+];
+
+const FRACTAL_TREE: [Coefficients; 4] = [
+    [0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.05],
+    [0.42, -0.42, 0.42, 0.42, 0.0, 0.2, 0.40],
+    [0.42, 0.42, -0.42, 0.42, 0.0, 0.2, 0.40],
+    [0.1, 0.0, 0.0, 0.1, 0.0, 0.2, 0.15],
+];
+
+/// Named classic iterated function systems.
+///
+/// Names parse from and display as kebab-case, e.g. `"barnsley-fern"`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Preset {
+    BarnsleyFern,
+    SierpinskiTriangle,
+    SierpinskiCarpet,
+    Vicsek,
+    HeighwayDragon,
+    KochCurve,
+    LevyCCurve,
+    MapleLeaf,
+    FractalTree,
+}
+
+impl Preset {
+    fn coefficients(&self) -> &'static [Coefficients] {
+        match self {
+            Preset::BarnsleyFern => &BARNSLEY_FERN,
+            Preset::SierpinskiTriangle => &SIERPINSKI_TRIANGLE,
+            Preset::SierpinskiCarpet => &SIERPINSKI_CARPET,
+            Preset::Vicsek => &VICSEK,
+            Preset::HeighwayDragon => &HEIGHWAY_DRAGON,
+            Preset::KochCurve => &KOCH_CURVE,
+            Preset::LevyCCurve => &LEVY_C_CURVE,
+            Preset::MapleLeaf => &MAPLE_LEAF,
+            Preset::FractalTree => &FRACTAL_TREE,
+        }
+    }
+
+    /// The transforms of the preset, already fit to the image
+    pub fn transforms(&self) -> Vec<Transform> {
+        let coefficients = self.coefficients();
+        let (center, scale) = fit_to_view(coefficients);
+
+        coefficients.iter().enumerate().map(|(i, &[a, b, c, d, e, f, p])| {
+            // conjugate by q = scale * R (x - center) where R turns the math frame's "up"
+            // into the rendered image's "up", i.e. R(x, y) = (-y, x)
+            let shift_x = a * center.0 + b * center.1 + e - center.0;
+            let shift_y = c * center.0 + d * center.1 + f - center.1;
+            AffineTransform::new(d, -c, -b, a,
+                                 -scale * shift_y, scale * shift_x,
+                                 PALETTE[i % PALETTE.len()], p).into()
+        }).collect()
+    }
+
+    /// Build an IFS from the preset
+    pub fn ifs(&self) -> IFS {
+        IFS::from_transforms(self.transforms(), None)
+    }
+
+    /// Build a config for the preset with default image and evaluation settings, saving to `<name>.png`
+    pub fn config(&self) -> Config {
+        Config {
+            version: SCHEMA_VERSION,
+            image_settings: ImageSettings{width: 1000, height: 1000, path: format!("{}.png", self),
+                                          output_format: None, hdr_stage: HdrStage::default(), preview: None,
+                                          tile_rows: None, background: None, transparent: false},
+            evaluation_settings: EvaluationSettings{num_iterations: 10000, num_points: 1000, seed: None,
+                                                    checkpoint_every: None, target: None},
+            transforms: self.transforms(),
+            transitions: None,
+            labels: vec![],
+        }
+    }
+}
+
+/// Find the center and scale that fit the attractor of the classic maps into the view,
+/// by running a short fixed-seed chaos game to find its bounding box.
+fn fit_to_view(coefficients: &[Coefficients]) -> ((f32, f32), f32) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let distribution = WeightedIndex::new(coefficients.iter().map(|m| m[6])).unwrap();
+
+    let (mut x, mut y) = (0.0f32, 0.0f32);
+    let (mut min_x, mut max_x, mut min_y, mut max_y) = (f32::MAX, f32::MIN, f32::MAX, f32::MIN);
+    for i in 0..50_000 {
+        let [a, b, c, d, e, f, _] = coefficients[distribution.sample(&mut rng)];
+        (x, y) = (a * x + b * y + e, c * x + d * y + f);
+        if i > 100 {
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+    }
+
+    let extent = (max_x - min_x).max(max_y - min_y);
+    let scale = if extent > 0.0 { 2.0 * VIEW_HALF_WIDTH / extent } else { 1.0 };
+    (((min_x + max_x) / 2.0, (min_y + max_y) / 2.0), scale)
+}
+
+impl IFS {
+    /// Build an IFS from a named preset
+    pub fn from_preset(preset: Preset) -> IFS {
+        preset.ifs()
+    }
+
+    /// Barnsley's fern
+    pub fn barnsley_fern() -> IFS {
+        Preset::BarnsleyFern.ifs()
+    }
+
+    /// Sierpinski triangle
+    pub fn sierpinski_triangle() -> IFS {
+        Preset::SierpinskiTriangle.ifs()
+    }
+
+    /// Sierpinski carpet
+    pub fn sierpinski_carpet() -> IFS {
+        Preset::SierpinskiCarpet.ifs()
+    }
+
+    /// Vicsek fractal, a cross-shaped relative of the Sierpinski carpet
+    pub fn vicsek() -> IFS {
+        Preset::Vicsek.ifs()
+    }
+
+    /// Heighway dragon curve
+    pub fn heighway_dragon() -> IFS {
+        Preset::HeighwayDragon.ifs()
+    }
+
+    /// Koch curve
+    pub fn koch_curve() -> IFS {
+        Preset::KochCurve.ifs()
+    }
+
+    /// Lévy C curve
+    pub fn levy_c_curve() -> IFS {
+        Preset::LevyCCurve.ifs()
+    }
+
+    /// Barnsley's maple leaf
+    pub fn maple_leaf() -> IFS {
+        Preset::MapleLeaf.ifs()
+    }
+
+    /// Barnsley's fractal tree
+    pub fn fractal_tree() -> IFS {
+        Preset::FractalTree.ifs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use strum::IntoEnumIterator;
+    use crate::ifs::PointSink;
+    use crate::presets::Preset;
+    use crate::transform::final_transform;
+    use crate::util::Color;
+
+    /// Largest distance from the center of the image of the points after the first few of a trajectory
+    struct Extent {
+        seen: usize,
+        largest: f32
+    }
+
+    impl PointSink for Extent {
+        fn add_point(&mut self, x: f32, y: f32, _color: Color, _transform: usize) {
+            self.seen += 1;
+            if self.seen > 20 {
+                let (fx, fy) = final_transform(x, y);
+                self.largest = self.largest.max(fx.abs()).max(fy.abs());
+            }
+        }
+    }
+
+    #[test]
+    fn test_presets_fit_the_image() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for preset in Preset::iter() {
+            let ifs = preset.ifs();
+            let mut extent = Extent{seen: 0, largest: 0.0};
+            for _ in 0..50 {
+                extent.seen = 0;
+                ifs.evaluate_into(&mut extent, 1, 1000, &mut rng);
+            }
+            assert!(extent.largest <= 1.0, "{} reaches {} from the center", preset, extent.largest);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! JSON Schemas describing config and template files
+//!
+//! Editors and form generators can validate files against these schemas to catch mistakes,
+//! like a misspelled transform name, before running them.
+//!
+//! ```rust
+//! use barnsley::schema::config_schema;
+//!
+//! let schema = serde_json::to_string_pretty(&config_schema()).unwrap();
+//! assert!(schema.contains("AffineTransform"));
+//! ```
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject};
+use schemars::schema_for;
+use strum::IntoEnumIterator;
+use crate::animation::AnimationConfig;
+use crate::config::Config;
+use crate::template::Template;
+use crate::transform::{Transform, Transformable};
+
+/// Schema for a config file, including every transform variant
+pub fn config_schema() -> RootSchema {
+    schema_for!(Config)
+}
+
+/// Schema for a template file
+pub fn template_schema() -> RootSchema {
+    schema_for!(Template)
+}
+
+/// Schema for an animation file
+pub fn animation_schema() -> RootSchema {
+    schema_for!(AnimationConfig)
+}
+
+/// Schema for a single transform, any of the variants
+pub fn transform_schema() -> RootSchema {
+    schema_for!(Transform)
+}
+
+/// Names of every transform, as used in templates
+pub fn transform_names() -> Vec<String> {
+    Transform::iter().map(|t| t.get_name()).collect()
+}
+
+/// Schema for a list of transform names, limited to the names that exist
+pub(crate) fn transform_names_schema(gen: &mut SchemaGenerator) -> Schema {
+    let name = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(transform_names().into_iter().map(|n| n.into()).collect()),
+        ..Default::default()
+    };
+    let mut list: SchemaObject = <Vec<String> as schemars::JsonSchema>::json_schema(gen).into_object();
+    list.array().items = Some(Schema::Object(name).into());
+    Schema::Object(list)
+}
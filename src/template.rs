@@ -1,23 +1,53 @@
 //! representation of an non-parameterized, random IFS
 
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use crate::transform::*;
 use crate::config::*;
+use crate::transition::Transitions;
 
-#[derive(Serialize, Deserialize)]
+/// Templates define an IFS run like a `Config`, but only name the kinds of transforms. 
+/// Their parameters are picked at random each time a config is generated.
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct Template {
+   /// file format version, see `SCHEMA_VERSION`
+   #[serde(default)]
+   pub version: u32,
+   /// size and location of the output image
    pub image_settings: ImageSettings,
+   /// how much work goes into the image
    pub evaluation_settings: EvaluationSettings,
+   /// names of the transforms to generate with random parameters, e.g. "AffineTransform"
+   #[schemars(schema_with = "crate::schema::transform_names_schema")]
    pub random_transforms: Vec<String>,
+   /// when true a random transition matrix is generated, making a recurrent IFS
+   #[serde(default, skip_serializing_if = "is_default")]
+   pub random_transitions: bool,
 }
 
 impl Template {
+    /// Bring a template read from an older file up to the current `SCHEMA_VERSION`.
+    /// 
+    /// Panics if the template comes from a newer version of the crate.
+    pub fn migrate(mut self) -> Template {
+        if self.version > SCHEMA_VERSION {
+            panic!("template has version {} but the newest supported version is {}", self.version, SCHEMA_VERSION);
+        }
+        self.version = SCHEMA_VERSION;
+        self
+    }
+
     /// Generate a `Config` from the `Template`
     pub fn generate(&self) -> Config {
         let mut transforms: Vec<Transform> = vec![];
         for transform_name in self.random_transforms.clone() {
             transforms.insert(transforms.len(), transform_from_str(transform_name));
         }
-        Config{image_settings: self.image_settings.clone(), evaluation_settings: self.evaluation_settings, transforms}
+        let transitions = if self.random_transitions {
+            Some(Transitions::random(transforms.len()))
+        } else {
+            None
+        };
+        Config{version: SCHEMA_VERSION, image_settings: self.image_settings.clone(), evaluation_settings: self.evaluation_settings, transforms, transitions, labels: vec![]}
     }
 }
\ No newline at end of file
@@ -0,0 +1,163 @@
+//! rendering images too large to hold in memory, one band of rows at a time
+//!
+//! With `tile_rows` set in the image settings, `Config::run` renders the image as tiles of that many rows
+//! (see `Image::tile`). Every tile draws all the points, seeded the same, and keeps only the ones landing in it,
+//! so the tiles add up to exactly the image an untiled render makes. Each point lands in a single pixel, so tiles
+//! need no margin around them. Rendering in `n` tiles therefore runs the chaos game `n` times over, trading time for memory.
+//!
+//! Finished tiles are saved as accumulation buffers in `<path>.tiles`, next to the config with its seed, which also
+//! lets `barnsley evaluate --resume` skip the tiles already done, as long as the config has not changed since.
+//! Once all are rendered, the exposure is computed over the whole image and the PNG is streamed to disk
+//! a tile at a time, then the directory is removed.
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::checkpoint::without_seed;
+use crate::config::Config;
+use crate::image::{Background, Image, OutputFormat};
+use crate::metadata::{config_text, png_bytes, stream_png_with_text};
+
+/// Directory the finished tiles of a render to `image_path` are kept in
+pub fn tile_directory(image_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.tiles", image_path))
+}
+
+impl Config {
+    /// Render and save the image a tile of `tile_rows` rows at a time, continuing from the finished tiles if `resume` is set.
+    ///
+    /// Only PNG output, 8 or 16-bit, can be streamed. Any checkpoint, preview or quality target is ignored,
+    /// as the tiles must all draw the same points.
+    pub fn run_tiled(mut self, tile_rows: usize, resume: bool) -> Result<(), String> {
+        let sixteen_bit = match self.image_settings.output_format() {
+            OutputFormat::EightBit if self.image_settings.path.to_lowercase().ends_with(".png") => false,
+            OutputFormat::Png16 => true,
+            format => return Err(format!("tiled renders can only be saved as PNG, not {:?}", format))
+        };
+        let background = self.image_settings.background();
+        let color = match background {
+            Background::Transparent => png::ColorType::Rgba,
+            Background::Color(_) => png::ColorType::Rgb
+        };
+        let depth = if sixteen_bit { png::BitDepth::Sixteen } else { png::BitDepth::Eight };
+        let directory = tile_directory(&self.image_settings.path);
+        let config_path = directory.join("config.json");
+        let saved = if resume && config_path.exists() {
+            let data = fs::read_to_string(&config_path).map_err(|e| format!("could not open {}: {}", config_path.display(), e))?;
+            let saved: Config = serde_json::from_str(&data)
+                .map_err(|e| format!("could not parse the config in {}: {}", config_path.display(), e))?;
+            Some(saved)
+        } else {
+            None
+        };
+        if let Some(saved) = &saved {
+            // tiles of a changed config would not fit together with the ones rendered now
+            if without_seed(saved) != without_seed(&self) {
+                return Err(format!("{} was saved by a different config, remove {} to start over",
+                                   config_path.display(), directory.display()));
+            }
+            if let Some(seed) = saved.evaluation_settings.seed {
+                self.evaluation_settings.seed = Some(seed);
+            }
+        }
+        // tiles without the config that rendered them can not be trusted
+        let resume = saved.is_some();
+        let seed = *self.evaluation_settings.seed.get_or_insert_with(rand::random);
+        fs::create_dir_all(&directory).map_err(|e| format!("could not create {}: {}", directory.display(), e))?;
+        fs::write(&config_path, serde_json::to_string(&self).unwrap())
+            .map_err(|e| format!("could not write {}: {}", config_path.display(), e))?;
+
+        let mut tile_config = self.clone();
+        tile_config.evaluation_settings.checkpoint_every = None;
+        tile_config.evaluation_settings.target = None;
+        tile_config.image_settings.preview = None;
+
+        let (width, height) = (self.image_settings.width as usize, self.image_settings.height as usize);
+        let tile_rows = tile_rows.max(1);
+        let tiles: Vec<_> = (0..width).step_by(tile_rows).map(|start| start..width.min(start + tile_rows)).collect();
+        let tile_path = |index: usize| directory.join(format!("{:06}.acc", index));
+
+        // render every tile, adding up the statistics needed to expose them all the same
+        let mut sum_of_logs = 0.0f64;
+        let mut samples_per_pixel = 1;
+        for (index, xs) in tiles.iter().enumerate() {
+            let path = tile_path(index);
+            let tile = if resume && path.exists() {
+                Image::load_accumulation(&path)?
+            } else {
+                let mut tile = Image::tile(width, height, xs.clone());
+                tile_config.render(&mut tile);
+                let unfinished = path.with_extension("acc.tmp");
+                tile.save_accumulation(&unfinished)?;
+                fs::rename(&unfinished, &path).map_err(|e| format!("could not move {}: {}", unfinished.display(), e))?;
+                tile
+            };
+            samples_per_pixel = 1.max(tile.samples() as usize / (width * height));
+            sum_of_logs += tile.log_luminance_sum(samples_per_pixel) as f64;
+        }
+        let log_mean_luminance = 10.0f32.powf((sum_of_logs / (width * height) as f64) as f32);
+
+        let path = Path::new(&self.image_settings.path);
+        stream_png_with_text(path, height as u32, width as u32, color, depth, &config_text(&self, seed), |stream| {
+            for index in 0..tiles.len() {
+                let tile = Image::load_accumulation(&tile_path(index))?;
+                let rows = tile.to_display_image(samples_per_pixel, log_mean_luminance, sixteen_bit, background);
+                stream.write_all(&png_bytes(&rows)).map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+            }
+            Ok(())
+        })?;
+        fs::remove_dir_all(&directory).map_err(|e| format!("could not remove {}: {}", directory.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::presets::Preset;
+    use crate::tiles::tile_directory;
+
+    #[test]
+    fn test_tiles_match_whole_image() {
+        let path = crate::util::test_path("tiles.png");
+        let path = path.to_str().unwrap().to_string();
+        let mut config = Preset::BarnsleyFern.config();
+        config.image_settings.path = path.clone();
+        config.image_settings.width = 30;
+        config.image_settings.height = 20;
+        config.evaluation_settings.num_points = 50;
+        config.evaluation_settings.num_iterations = 100;
+        config.evaluation_settings.seed = Some(11);
+
+        config.clone().run();
+        let whole = image::open(&path).unwrap().to_rgb8();
+
+        config.image_settings.tile_rows = Some(7);
+        config.run();
+        let tiled = image::open(&path).unwrap().to_rgb8();
+        assert!(!tile_directory(&path).exists());
+        assert_eq!(tiled.dimensions(), whole.dimensions());
+
+        // the exposure is summed tile by tile, so it can be off by a rounding step
+        let most_different = whole.as_raw().iter().zip(tiled.as_raw())
+            .map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+        assert!(most_different <= 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_refuses_changed_config() {
+        let mut config = crate::config::test_config("tiles_changed");
+        config.image_settings.tile_rows = Some(5);
+        config.evaluation_settings.seed = Some(3);
+        let directory = tile_directory(&config.image_settings.path);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("config.json"), serde_json::to_string(&config).unwrap()).unwrap();
+
+        let mut changed = config.clone();
+        changed.evaluation_settings.num_iterations = 11;
+        assert!(changed.run_tiled(5, true).unwrap_err().contains("different config"));
+
+        // the same config, even without its seed, resumes
+        config.evaluation_settings.seed = None;
+        config.clone().run_tiled(5, true).unwrap();
+        assert!(!directory.exists());
+        std::fs::remove_file(&config.image_settings.path).unwrap();
+    }
+}
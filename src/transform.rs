@@ -6,7 +6,8 @@
 //! For example, look at `LinearTransform` to understand what each part does.
 //!
 //! 1. Create a struct to store the transforms parameters. It should have a `base_color` and `weight` too.
-//! 2. Derive `Serialize, Deserialize, Copy, Clone, Debug` for the new transform struct.
+//! 2. Derive `Serialize, Deserialize, JsonSchema, Copy, Clone, Debug` for the new transform struct.
+//!    Document each field, the doc comments become descriptions in the JSON Schema.
 //! 3. Implement the `transform` trait for that struct.
 //! 4. Add the transform to the `Transform` enum.
 
@@ -14,6 +15,7 @@ use crate::util::*;
 use num::complex::{Complex, Complex32};
 use rand::prelude::*;
 use rand_distr::{Distribution, Normal};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 use std::default::Default;
@@ -33,7 +35,7 @@ pub fn final_transform(x: f32, y: f32) -> (f32, f32) {
 }
 
 #[enum_dispatch(Transformable)]
-#[derive(Serialize, Deserialize, Copy, Clone, EnumIter, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, EnumIter, PartialEq)]
 pub enum Transform {
     LinearTransform,
     AffineTransform,
@@ -52,6 +54,36 @@ impl Transform {
         }
     }
 
+    /// The same transform with its weight replaced by `weight`
+    pub fn with_weight(self, weight: f32) -> Transform {
+        match self {
+            Transform::LinearTransform(mut t) => { t.weight = weight; t.into() },
+            Transform::AffineTransform(mut t) => { t.weight = weight; t.into() },
+            Transform::MoebiusTransform(mut t) => { t.weight = weight; t.into() },
+            Transform::InverseJuliaTransform(mut t) => { t.weight = weight; t.into() },
+        }
+    }
+
+    /// Weighted combination of transforms of the same type, with `coefficients` summing to one. 
+    /// 
+    /// Blending two transforms is the same as `morph`. Coefficients outside [0, 1], as used by spline 
+    /// interpolation, are allowed. The weight of the result is kept from going negative. 
+    pub fn blend(transforms: &[Transform], coefficients: &[f32]) -> Transform {
+        fn all<T>(transforms: &[Transform], f: impl Fn(&Transform) -> Option<T>) -> Vec<T> {
+            transforms.iter().map(|t| f(t).expect("transforms must all be the same type")).collect()
+        }
+        match transforms.first().expect("at least one transform is needed") {
+            Transform::LinearTransform(_) => LinearTransform::blend(&all(transforms, |t| match t {
+                Transform::LinearTransform(t) => Some(*t), _ => None }), coefficients).into(),
+            Transform::AffineTransform(_) => AffineTransform::blend(&all(transforms, |t| match t {
+                Transform::AffineTransform(t) => Some(*t), _ => None }), coefficients).into(),
+            Transform::MoebiusTransform(_) => MoebiusTransform::blend(&all(transforms, |t| match t {
+                Transform::MoebiusTransform(t) => Some(*t), _ => None }), coefficients).into(),
+            Transform::InverseJuliaTransform(_) => InverseJuliaTransform::blend(&all(transforms, |t| match t {
+                Transform::InverseJuliaTransform(t) => Some(*t), _ => None }), coefficients).into(),
+        }
+    }
+
     pub fn index(&self) -> usize {
         match self {
             Transform::LinearTransform(_) => 0,
@@ -81,6 +113,11 @@ pub trait Transformable {
     /// Applies the transformation to a point
     fn transform_point(&self, point: Point) -> Point;
 
+    /// Applies the transformation to a point, drawing any random choices it makes from `rng`
+    fn transform_point_with_rng<R: Rng>(&self, point: Point, _rng: &mut R) -> Point {
+        self.transform_point(point)
+    }
+
     /// Retrieves the transforms weight
     fn get_weight(&self) -> f32;
 
@@ -106,13 +143,20 @@ pub fn transform_from_str(name: String) -> Transform {
 /// LinearTransform defined by the matrix:
 /// [a b]
 /// [c d]
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
 pub struct LinearTransform {
+    /// top left of the matrix, random values are in [-1, 1]
     pub a: f32,
+    /// top right of the matrix, random values are in [-1, 1]
     pub b: f32,
+    /// bottom left of the matrix, random values are in [-1, 1]
     pub c: f32,
+    /// bottom right of the matrix, random values are in [-1, 1]
     pub d: f32,
+    /// color mixed into points this transform is applied to
     pub base_color: Color,
+    /// relative likelihood of picking this transform
+    #[schemars(range(min = 0.0))]
     pub weight: f32,
 }
 
@@ -145,14 +189,21 @@ impl LinearTransform {
         }
     }
 
+    /// Interpolate by rotation angle and log of scale, see `blend_linear_part`
     fn morph(&self, other:&Self, pct: f32) -> Self{
-       LinearTransform::new(
-                    lerp_f32(self.a, other.a, pct),
-                    lerp_f32(self.b, other.b, pct),
-                    lerp_f32(self.c, other.c, pct),
-                    lerp_f32(self.d, other.d, pct),
-                    lerp_color(self.base_color, other.base_color, pct),
-                    lerp_f32(self.weight, other.weight, pct))
+        LinearTransform::blend(&[*self, *other], &[1.0 - pct, pct])
+    }
+
+    fn blend(transforms: &[Self], coefficients: &[f32]) -> Self {
+        let blend = |f: fn(&Self) -> f32| blend_f32(transforms.iter().map(f), coefficients);
+        let [a, b, c, d] = blend_linear_part(&transforms.iter().map(|t| [t.a, t.b, t.c, t.d]).collect::<Vec<_>>(), coefficients);
+        LinearTransform::new(
+                    a,
+                    b,
+                    c,
+                    d,
+                    blend_color(transforms.iter().map(|t| t.base_color), coefficients),
+                    blend(|t| t.weight).max(0.0))
     }
 }
 
@@ -184,31 +235,40 @@ impl Transformable for LinearTransform {
 }
 impl Morphable<LinearTransform> for LinearTransform {
     fn morph(&self, other: Box<&Self>, pct: f32) -> Box<LinearTransform> where Self: Sized {
-        Box::new(LinearTransform::new(
-                    lerp_f32(self.a, other.a, pct),
-                    lerp_f32(self.b, other.b, pct),
-                    lerp_f32(self.c, other.c, pct),
-                    lerp_f32(self.d, other.d, pct),
-                    lerp_color(self.base_color, other.base_color, pct),
-                    lerp_f32(self.weight, other.weight, pct)))
+        Box::new(LinearTransform::morph(self, *other, pct))
     }
 
 }
 
 // AFFINE TRANSFORM
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+/// AffineTransform defined by the matrix and shift:
+/// [a b] [x] + [x_shift]
+/// [c d] [y]   [y_shift]
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
 pub struct AffineTransform {
+    /// top left of the matrix, random values are in [-1, 1]
     pub a: f32,
+    /// top right of the matrix, random values are in [-1, 1]
     pub b: f32,
+    /// bottom left of the matrix, random values are in [-1, 1]
     pub c: f32,
+    /// bottom right of the matrix, random values are in [-1, 1]
     pub d: f32,
+    /// shift added to x, random values are in [-2, 2]
+    #[serde(alias = "xshift")]
     pub x_shift: f32,
+    /// shift added to y, random values are in [-2, 2]
+    #[serde(alias = "yshift")]
     pub y_shift: f32,
+    /// color mixed into points this transform is applied to
     pub base_color: Color,
+    /// relative likelihood of picking this transform
+    #[schemars(range(min = 0.0))]
     pub weight: f32,
 }
 
 impl AffineTransform {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         a: f32,
         b: f32,
@@ -255,19 +315,25 @@ impl AffineTransform {
         }
     }
 
+   /// Interpolate the linear part by rotation angle and log of scale, see `blend_linear_part`,
+   /// and the shift linearly
    fn morph(&self, other: &Self, pct: f32) -> Self {
-       AffineTransform::new(
-           lerp_f32(self.a, other.a, pct),
-           lerp_f32(self.b, other.b, pct),
-           lerp_f32(self.c, other.c, pct),
-           lerp_f32(self.d, other.d, pct),
-           lerp_f32(self.x_shift, other.x_shift, pct),
-           lerp_f32(self.y_shift, other.y_shift, pct),
-           lerp_color(self.base_color, other.base_color, pct),
-           lerp_f32(self.weight, other.weight, pct))
+       AffineTransform::blend(&[*self, *other], &[1.0 - pct, pct])
     }
 
-
+    fn blend(transforms: &[Self], coefficients: &[f32]) -> Self {
+        let blend = |f: fn(&Self) -> f32| blend_f32(transforms.iter().map(f), coefficients);
+        let [a, b, c, d] = blend_linear_part(&transforms.iter().map(|t| [t.a, t.b, t.c, t.d]).collect::<Vec<_>>(), coefficients);
+        AffineTransform::new(
+            a,
+            b,
+            c,
+            d,
+            blend(|t| t.x_shift),
+            blend(|t| t.y_shift),
+            blend_color(transforms.iter().map(|t| t.base_color), coefficients),
+            blend(|t| t.weight).max(0.0))
+    }
 }
 
 impl Default for AffineTransform {
@@ -299,26 +365,31 @@ impl Transformable for AffineTransform {
 
 impl Morphable<AffineTransform> for AffineTransform {
     fn morph(&self, other: Box<&Self>, pct: f32) -> Box<Self> where Self: Sized {
-        Box::new(AffineTransform::new(
-            lerp_f32(self.a, other.a, pct),
-            lerp_f32(self.b, other.b, pct),
-            lerp_f32(self.c, other.c, pct),
-            lerp_f32(self.d, other.d, pct),
-            lerp_f32(self.x_shift, other.x_shift, pct),
-            lerp_f32(self.y_shift, other.y_shift, pct),
-            lerp_color(self.base_color, other.base_color, pct),
-            lerp_f32(self.weight, other.weight, pct)))
+        Box::new(AffineTransform::morph(self, *other, pct))
     }
 }
 
 // MOEBIUS TRANSFORM
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+/// MoebiusTransform defined on complex numbers as (a z + b) / (c z + d).
+/// Complex numbers are written as `[re, im]`.
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
 pub struct MoebiusTransform {
+    /// random values have real and imaginary parts in [-1, 1]
+    #[schemars(with = "[f32; 2]")]
     pub a: Complex<f32>,
+    /// random values have real and imaginary parts in [-1, 1]
+    #[schemars(with = "[f32; 2]")]
     pub b: Complex32,
+    /// random values have real and imaginary parts in [-1, 1]
+    #[schemars(with = "[f32; 2]")]
     pub c: Complex32,
+    /// random values have real and imaginary parts in [-1, 1]
+    #[schemars(with = "[f32; 2]")]
     pub d: Complex32,
+    /// color mixed into points this transform is applied to
     pub base_color: Color,
+    /// relative likelihood of picking this transform
+    #[schemars(range(min = 0.0))]
     pub weight: f32,
 }
 
@@ -362,14 +433,20 @@ impl MoebiusTransform {
         }
     }
 
+    /// Interpolate in normalized PSL(2,C) form, see `blend_moebius`
     fn morph(&self, other: &Self, pct: f32) -> Self{
-       MoebiusTransform::new(
-                    lerp_complex32(self.a, other.a, pct),
-                    lerp_complex32(self.b, other.b, pct),
-                    lerp_complex32(self.c, other.c, pct),
-                    lerp_complex32(self.d, other.d, pct),
-                    lerp_color(self.base_color, other.base_color, pct),
-                    lerp_f32(self.weight, other.weight, pct))
+        MoebiusTransform::blend(&[*self, *other], &[1.0 - pct, pct])
+    }
+
+    fn blend(transforms: &[Self], coefficients: &[f32]) -> Self {
+        let [a, b, c, d] = blend_moebius(&transforms.iter().map(|t| [t.a, t.b, t.c, t.d]).collect::<Vec<_>>(), coefficients);
+        MoebiusTransform::new(
+                    a,
+                    b,
+                    c,
+                    d,
+                    blend_color(transforms.iter().map(|t| t.base_color), coefficients),
+                    blend_f32(transforms.iter().map(|t| t.weight), coefficients).max(0.0))
     }
 }
 
@@ -404,23 +481,23 @@ impl Transformable for MoebiusTransform {
 
 impl Morphable<MoebiusTransform> for MoebiusTransform {
     fn morph(&self, other: Box<&Self>, pct: f32) -> Box<Self> where Self: Sized {
-        Box::new(MoebiusTransform::new(
-                    lerp_complex32(self.a, other.a, pct),
-                    lerp_complex32(self.b, other.b, pct),
-                    lerp_complex32(self.c, other.c, pct),
-                    lerp_complex32(self.d, other.d, pct),
-                    lerp_color(self.base_color, other.base_color, pct),
-                    lerp_f32(self.weight, other.weight, pct)))
+        Box::new(MoebiusTransform::morph(self, *other, pct))
     }
 }
 
 // INVERSE JULIA TRANSFORM
-
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+/// InverseJuliaTransform, one of the two branches of the inverse of z^2 + c, 
+/// where c has magnitude `r` and angle `theta`
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
 pub struct InverseJuliaTransform {
+    /// magnitude of c, random values are in [0.8, 1.2]
     pub r: f32,
+    /// angle of c in radians, random values are in [0, 2 pi]
     pub theta: f32,
+    /// color mixed into points this transform is applied to
     pub base_color: Color,
+    /// relative likelihood of picking this transform
+    #[schemars(range(min = 0.0))]
     pub weight: f32
 }
 
@@ -453,6 +530,15 @@ impl InverseJuliaTransform {
                     lerp_color(self.base_color, other.base_color, pct),
                     lerp_f32(self.weight, other.weight, pct))
     }
+
+    fn blend(transforms: &[Self], coefficients: &[f32]) -> Self {
+        let blend = |f: fn(&Self) -> f32| blend_f32(transforms.iter().map(f), coefficients);
+        InverseJuliaTransform::new(
+                    blend(|t| t.r),
+                    blend(|t| t.theta),
+                    blend_color(transforms.iter().map(|t| t.base_color), coefficients),
+                    blend(|t| t.weight).max(0.0))
+    }
 }
 
 
@@ -468,6 +554,10 @@ impl Transformable for InverseJuliaTransform {
     }
 
     fn transform_point(&self, point: Point) -> Point {
+        self.transform_point_with_rng(point, &mut thread_rng())
+    }
+
+    fn transform_point_with_rng<R: Rng>(&self, point: Point, rng: &mut R) -> Point {
         let c = Complex32::new(self.r * self.theta.cos(), self.r * self.theta.sin());
 
         let z = Complex32 {
@@ -476,7 +566,7 @@ impl Transformable for InverseJuliaTransform {
         };
         let z2 = c - z;
         let new_theta = z2.im.atan2(z2.re) * 0.5;
-        let sqrt_r = vec![1., -1.].choose(&mut thread_rng()).unwrap()
+        let sqrt_r = [1., -1.].choose(rng).unwrap()
             * ((z2.im * z2.im + z2.re * z2.re).powf(0.25));
         Point {
             x: sqrt_r * new_theta.cos(),
@@ -0,0 +1,226 @@
+//! transition rules for recurrent (Markov) iterated function systems
+//!
+//! A normal IFS picks each transform independently according to its weight. A recurrent IFS
+//! instead picks the next transform conditioned on the one that was just applied.
+//! The rules can be given either as a full transition-probability matrix, where entry `[i][j]`
+//! is the probability of applying transform `j` right after transform `i`, or as a list of
+//! the transforms allowed to follow each transform. In the latter case the choice between the
+//! allowed transforms is made according to their weights.
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How far a row of a transition matrix may stray from summing to one
+pub const ROW_SUM_TOLERANCE: f32 = 1e-3;
+
+/// A problem with a set of transitions
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransitionError {
+    /// the row of the matrix or allowed-next list at fault, `None` if the problem is with the whole
+    pub row: Option<usize>,
+    /// what is wrong
+    pub message: String
+}
+
+impl TransitionError {
+    fn new(row: Option<usize>, message: String) -> TransitionError {
+        TransitionError { row, message }
+    }
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Rules for picking the next transform based on the previous one
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum Transitions {
+    /// NxN matrix where `matrix[i][j]` is the probability of transform `j` following transform `i`
+    Matrix(Vec<Vec<f32>>),
+    /// `allowed[i]` lists the indices of the transforms that may follow transform `i`
+    AllowedNext(Vec<Vec<usize>>),
+}
+
+impl Transitions {
+    /// Generate a random transition matrix for `num_transforms` transforms.
+    ///
+    /// Roughly a third of the entries are zeroed out so the result is noticeably different
+    /// from an ordinary IFS, but every row keeps at least one allowed successor.
+    pub fn random(num_transforms: usize) -> Transitions {
+        let mut rng = thread_rng();
+        let mut matrix = vec![];
+        for _ in 0..num_transforms {
+            let mut row: Vec<f32> = (0..num_transforms)
+                .map(|_| if rng.gen::<f32>() < 0.33 { 0.0 } else { rng.gen::<f32>() })
+                .collect();
+            if row.iter().all(|&p| p == 0.0) {
+                row[rng.gen_range(0..num_transforms)] = 1.0;
+            }
+            let total: f32 = row.iter().sum();
+            matrix.push(row.iter().map(|p| p / total).collect());
+        }
+        Transitions::Matrix(matrix)
+    }
+
+    /// Transitions that let any transform follow any other, i.e. an ordinary IFS
+    pub fn independent(num_transforms: usize) -> Transitions {
+        Transitions::AllowedNext(vec![(0..num_transforms).collect(); num_transforms])
+    }
+
+    /// Check the transitions are usable with transforms of the given `weights`.
+    ///
+    /// Matrices must be square with the same size as the transform list, contain no negative
+    /// or non-finite entries, and have every row sum to one. Allowed-next lists must have one
+    /// non-empty entry per transform, only reference existing transforms, and allow at least
+    /// one transform with a positive weight in every row.
+    ///
+    /// Every problem found is returned, at most one per row, so an empty list means the transitions are usable.
+    pub fn validate(&self, weights: &[f32]) -> Vec<TransitionError> {
+        let num_transforms = weights.len();
+        let mut problems = vec![];
+        match self {
+            Transitions::Matrix(matrix) => {
+                if matrix.len() != num_transforms {
+                    problems.push(TransitionError::new(None, format!("transition matrix has {} rows but there are {} transforms",
+                                                                     matrix.len(), num_transforms)));
+                }
+                for (i, row) in matrix.iter().enumerate() {
+                    let total: f32 = row.iter().sum();
+                    let message = if row.len() != num_transforms {
+                        format!("row {} of the transition matrix has {} entries but there are {} transforms", i, row.len(), num_transforms)
+                    } else if row.iter().any(|p| !p.is_finite() || *p < 0.0) {
+                        format!("row {} of the transition matrix has a negative or non-finite entry", i)
+                    } else if (total - 1.0).abs() > ROW_SUM_TOLERANCE {
+                        format!("row {} of the transition matrix sums to {} instead of 1", i, total)
+                    } else {
+                        continue
+                    };
+                    problems.push(TransitionError::new(Some(i), message));
+                }
+            },
+            Transitions::AllowedNext(allowed) => {
+                if allowed.len() != num_transforms {
+                    problems.push(TransitionError::new(None, format!("allowed-next lists have {} entries but there are {} transforms",
+                                                                     allowed.len(), num_transforms)));
+                }
+                for (i, next) in allowed.iter().enumerate() {
+                    let message = if next.is_empty() {
+                        format!("transform {} has no allowed next transform", i)
+                    } else if let Some(j) = next.iter().find(|&&j| j >= num_transforms) {
+                        format!("transform {} lists transform {} as next but there are only {} transforms", i, j, num_transforms)
+                    } else if next.iter().all(|&j| weights[j] <= 0.0) {
+                        format!("every transform allowed after transform {} has a weight of zero", i)
+                    } else {
+                        continue
+                    };
+                    problems.push(TransitionError::new(Some(i), message));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Express the transitions as a full probability matrix, using `weights` to
+    /// distribute probability among allowed successors.
+    pub fn to_matrix(&self, weights: &[f32]) -> Vec<Vec<f32>> {
+        match self {
+            Transitions::Matrix(matrix) => matrix.clone(),
+            Transitions::AllowedNext(allowed) => allowed.iter().map(|next| {
+                let mut row = vec![0.0; weights.len()];
+                for &j in next {
+                    row[j] = weights[j];
+                }
+                let total: f32 = row.iter().sum();
+                row.iter().map(|p| p / total).collect()
+            }).collect()
+        }
+    }
+
+    /// Build one sampling distribution per row, i.e. per previous transform.
+    pub(crate) fn distributions(&self, weights: &[f32]) -> Vec<WeightedIndex<f32>> {
+        self.to_matrix(weights).iter()
+            .map(|row| WeightedIndex::new(row).expect("transition rows must have a positive entry"))
+            .collect()
+    }
+
+    /// Interpolate between two sets of transitions by blending their matrices.
+    ///
+    /// Returns `None` if they describe a different number of transforms.
+    pub fn morph(&self, other: &Self, self_weights: &[f32], other_weights: &[f32], pct: f32) -> Option<Transitions> {
+        Transitions::blend(&[(self, self_weights), (other, other_weights)], &[1.0 - pct, pct])
+    }
+
+    /// Weighted combination of several sets of transitions, each given with the weights of its transforms.
+    ///
+    /// Coefficients outside [0, 1] can push probabilities negative, so they are clipped at zero
+    /// and each row is normalized again. Returns `None` if they describe a different number of transforms.
+    pub fn blend(transitions: &[(&Transitions, &[f32])], coefficients: &[f32]) -> Option<Transitions> {
+        let matrices: Vec<Vec<Vec<f32>>> = transitions.iter().map(|(t, weights)| t.to_matrix(weights)).collect();
+        let size = matrices.first()?.len();
+        if matrices.iter().any(|m| m.len() != size) {
+            return None
+        }
+        Some(Transitions::Matrix((0..size).map(|i| {
+            let row: Vec<f32> = (0..size)
+                .map(|j| matrices.iter().zip(coefficients).map(|(m, c)| m[i][j] * c).sum::<f32>().max(0.0))
+                .collect();
+            let total: f32 = row.iter().sum();
+            if total > 0.0 {
+                row.iter().map(|p| p / total).collect()
+            } else {
+                vec![1.0 / size as f32; size]
+            }
+        }).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transition::Transitions;
+
+    #[test]
+    fn test_validate_matrix() {
+        let good = Transitions::Matrix(vec![vec![0.5, 0.5], vec![1.0, 0.0]]);
+        assert!(good.validate(&[1.0; 2]).is_empty());
+        assert!(!good.validate(&[1.0; 3]).is_empty());
+
+        let bad_sum = Transitions::Matrix(vec![vec![0.5, 0.4], vec![1.0, 0.0]]);
+        assert!(!bad_sum.validate(&[1.0; 2]).is_empty());
+
+        let negative = Transitions::Matrix(vec![vec![1.5, -0.5], vec![1.0, 0.0]]);
+        assert!(!negative.validate(&[1.0; 2]).is_empty());
+    }
+
+    #[test]
+    fn test_validate_allowed_next() {
+        assert!(Transitions::AllowedNext(vec![vec![1], vec![0, 1]]).validate(&[1.0; 2]).is_empty());
+        assert!(!Transitions::AllowedNext(vec![vec![], vec![0]]).validate(&[1.0; 2]).is_empty());
+        assert!(!Transitions::AllowedNext(vec![vec![2], vec![0]]).validate(&[1.0; 2]).is_empty());
+
+        // transform 1 may only be followed by transform 0, which can never be picked
+        let errors = Transitions::AllowedNext(vec![vec![1], vec![0]]).validate(&[0.0, 1.0]);
+        assert_eq!(errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![Some(1)]);
+
+        // every row at fault is reported
+        let errors = Transitions::AllowedNext(vec![vec![1], vec![0], vec![5]]).validate(&[0.0, 1.0, 1.0]);
+        assert_eq!(errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_allowed_next_to_matrix() {
+        let matrix = Transitions::AllowedNext(vec![vec![1, 2], vec![0], vec![0, 1, 2]])
+            .to_matrix(&[1.0, 1.0, 3.0]);
+        assert_eq!(matrix, vec![vec![0.0, 0.25, 0.75], vec![1.0, 0.0, 0.0], vec![0.2, 0.2, 0.6]]);
+    }
+
+    #[test]
+    fn test_random_is_valid() {
+        for n in 1..6 {
+            assert!(Transitions::random(n).validate(&vec![1.0; n]).is_empty());
+        }
+    }
+}
@@ -2,8 +2,9 @@
 
 use num::{complex::Complex32, FromPrimitive};
 use rand::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{default::Default, ops::{Add, Mul}};
+use std::{default::Default, f32::consts::PI, ops::{Add, Mul}};
 
 /// lerp between two floats
 pub fn lerp_f32(a: f32, b: f32, pct: f32) -> f32 {
@@ -12,7 +13,7 @@ pub fn lerp_f32(a: f32, b: f32, pct: f32) -> f32 {
 
 /// lerp between two complex
 pub fn lerp_complex32(a: Complex32, b: Complex32, pct: f32) -> Complex32 {
-    Complex32::new(lerp_f32(a.re, b.re, pct), lerp_f32(a.re, b.re, pct))
+    Complex32::new(lerp_f32(a.re, b.re, pct), lerp_f32(a.im, b.im, pct))
 }
 
 /// lerp between two colors
@@ -24,11 +25,125 @@ pub fn lerp_color(a: Color, b: Color, pct: f32) -> Color {
     }
 }
 
+/// weighted combination of floats, `coefficients` usually sum to one
+pub fn blend_f32(values: impl Iterator<Item = f32>, coefficients: &[f32]) -> f32 {
+    values.zip(coefficients).map(|(v, c)| v * c).sum()
+}
+
+/// weighted combination of complex numbers, `coefficients` usually sum to one
+pub fn blend_complex32(values: impl Iterator<Item = Complex32>, coefficients: &[f32]) -> Complex32 {
+    values.zip(coefficients).map(|(v, c)| v * c).sum()
+}
+
+/// weighted combination of colors, `coefficients` usually sum to one. Channels are kept in [0, 1].
+pub fn blend_color(values: impl Iterator<Item = Color> + Clone, coefficients: &[f32]) -> Color {
+    Color {
+        r: blend_f32(values.clone().map(|c| c.r), coefficients).clamp(0.0, 1.0),
+        g: blend_f32(values.clone().map(|c| c.g), coefficients).clamp(0.0, 1.0),
+        b: blend_f32(values.map(|c| c.b), coefficients).clamp(0.0, 1.0),
+    }
+}
+
+/// Smallest stretch allowed when taking the log of a singular matrix
+const MIN_STRETCH: f32 = 1e-6;
+
+/// Apply `f` to a symmetric matrix `[p, q; q, r]` through its eigenvalues
+fn symmetric_function(p: f32, q: f32, r: f32, f: impl Fn(f32) -> f32) -> (f32, f32, f32) {
+    let mean = (p + r) / 2.0;
+    let half_gap = (((p - r) / 2.0).powi(2) + q * q).sqrt();
+    if half_gap < 1e-12 {
+        return (f(mean), 0.0, f(mean))
+    }
+    // f(P) = (f(l1) + f(l2)) / 2 I + (f(l1) - f(l2)) / (l1 - l2) (P - mean I)
+    let (f1, f2) = (f(mean + half_gap), f(mean - half_gap));
+    let along = (f1 - f2) / (2.0 * half_gap);
+    let base = (f1 + f2) / 2.0;
+    (base + along * (p - mean), along * q, base + along * (r - mean))
+}
+
+/// Polar decomposition of a 2x2 matrix `[a, b, c, d]` into a rotation angle, the log of its
+/// symmetric stretch as `[p, q, r]`, and whether it also reflects
+fn polar_decomposition(m: [f32; 4]) -> (f32, [f32; 3], bool) {
+    let [a, mut b, c, mut d] = m;
+    let reflected = a * d - b * c < 0.0;
+    if reflected {
+        // flip the second column so the rest has a positive determinant
+        b = -b;
+        d = -d;
+    }
+    let angle = (c - b).atan2(a + d);
+    let (sin, cos) = angle.sin_cos();
+    // stretch = R(-angle) m, which is symmetric
+    let (p, q, r) = (cos * a + sin * c, cos * b + sin * d, -sin * b + cos * d);
+    let (p, q, r) = symmetric_function(p, q, r, |l| l.max(MIN_STRETCH).ln());
+    (angle, [p, q, r], reflected)
+}
+
+/// Weighted combination of 2x2 matrices `[a, b, c, d]`, `coefficients` usually sum to one.
+///
+/// Each matrix is split into a rotation and a stretch. Angles are combined taking the short way
+/// around and stretches are combined by their log, so blending two rotations stays a rotation and
+/// blending two scalings scales geometrically. Matrices that disagree on reflecting cannot be joined
+/// without passing through a degenerate matrix, so those are blended entry by entry.
+pub fn blend_linear_part(matrices: &[[f32; 4]], coefficients: &[f32]) -> [f32; 4] {
+    let decomposed: Vec<(f32, [f32; 3], bool)> = matrices.iter().map(|m| polar_decomposition(*m)).collect();
+    let reflected = decomposed[0].2;
+    if decomposed.iter().any(|d| d.2 != reflected) {
+        return [0, 1, 2, 3].map(|i| blend_f32(matrices.iter().map(|m| m[i]), coefficients))
+    }
+
+    let first_angle = decomposed[0].0;
+    let angle = blend_f32(decomposed.iter().map(|(angle, _, _)| {
+        let turn = (angle - first_angle).rem_euclid(2.0 * PI);
+        first_angle + if turn > PI { turn - 2.0 * PI } else { turn }
+    }), coefficients);
+    let [p, q, r] = [0, 1, 2].map(|i| blend_f32(decomposed.iter().map(|d| d.1[i]), coefficients));
+    let (p, q, r) = symmetric_function(p, q, r, f32::exp);
+
+    let (sin, cos) = angle.sin_cos();
+    let (a, b, c, d) = (cos * p - sin * q, cos * q - sin * r, sin * p + cos * q, sin * q + cos * r);
+    if reflected {
+        [a, -b, c, -d]
+    } else {
+        [a, b, c, d]
+    }
+}
+
+/// Scale a Moebius matrix `[a, b, c, d]` to determinant one, leaving degenerate matrices alone
+pub fn normalize_moebius(m: [Complex32; 4]) -> [Complex32; 4] {
+    let det = m[0] * m[3] - m[1] * m[2];
+    if det.norm() < 1e-12 {
+        return m
+    }
+    let scale = det.sqrt();
+    m.map(|z| z / scale)
+}
+
+/// Weighted combination of Moebius matrices `[a, b, c, d]` as elements of PSL(2,C).
+///
+/// Every matrix is scaled to determinant one, and since a matrix and its negative are the same map
+/// each is given the sign closest to the first before blending. The result is normalized again.
+pub fn blend_moebius(matrices: &[[Complex32; 4]], coefficients: &[f32]) -> [Complex32; 4] {
+    let normalized: Vec<[Complex32; 4]> = matrices.iter().map(|m| normalize_moebius(*m)).collect();
+    let first = normalized[0];
+    let aligned: Vec<[Complex32; 4]> = normalized.iter().map(|m| {
+        let distance = |sign: f32| (0..4).map(|i| (m[i] * sign - first[i]).norm_sqr()).sum::<f32>();
+        if distance(-1.0) < distance(1.0) { m.map(|z| -z) } else { *m }
+    }).collect();
+    normalize_moebius([0, 1, 2, 3].map(|i| blend_complex32(aligned.iter().map(|m| m[i]), coefficients)))
+}
+
 /// representation of an RGB color
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Copy, Clone, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct Color {
+    /// red, from 0 to 1
+    #[schemars(range(min = 0.0, max = 1.0))]
     pub r: f32,
+    /// green, from 0 to 1
+    #[schemars(range(min = 0.0, max = 1.0))]
     pub g: f32,
+    /// blue, from 0 to 1
+    #[schemars(range(min = 0.0, max = 1.0))]
     pub b: f32,
 }
 
@@ -107,3 +222,45 @@ pub fn random_complex_number() -> Complex32 {
         im: rng.gen::<f32>() * 2. - 1.,
     }
 }
+
+/// Path in the temporary directory for a test file named `name`, unique to this process so parallel test runs do not collide
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("barnsley_test_{}_{}", std::process::id(), name))
+}
+
+#[cfg(test)]
+mod tests {
+    use num::complex::Complex32;
+    use crate::util::{blend_linear_part, blend_moebius, lerp_complex32};
+
+    #[test]
+    fn test_lerp_complex32() {
+        let z = lerp_complex32(Complex32::new(0.0, 2.0), Complex32::new(1.0, 4.0), 0.5);
+        assert_eq!(z, Complex32::new(0.5, 3.0));
+    }
+
+    #[test]
+    fn test_blend_rotations() {
+        // halfway between a rotation by 0 and by 90 degrees at twice the size is 45 degrees at sqrt(2)
+        let m = blend_linear_part(&[[1.0, 0.0, 0.0, 1.0], [0.0, -2.0, 2.0, 0.0]], &[0.5, 0.5]);
+        let expected = [1.0, -1.0, 1.0, 1.0];
+        for i in 0..4 {
+            assert!((m[i] - expected[i]).abs() < 1e-5);
+        }
+
+        // reflections stay reflections
+        let m = blend_linear_part(&[[1.0, 0.0, 0.0, -1.0], [0.0, 1.0, 1.0, 0.0]], &[0.5, 0.5]);
+        assert!((m[0] * m[3] - m[1] * m[2] + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_blend_moebius() {
+        let one = Complex32::new(1.0, 0.0);
+        let zero = Complex32::new(0.0, 0.0);
+        // the identity scaled by -3 is the same map, so blending it with the identity gives the identity
+        let m = blend_moebius(&[[one, zero, zero, one], [-one * 3.0, zero, zero, -one * 3.0]], &[0.5, 0.5]);
+        assert!((m[0] - one).norm() < 1e-5 && (m[3] - one).norm() < 1e-5);
+        assert!(m[1].norm() < 1e-5 && m[2].norm() < 1e-5);
+    }
+}
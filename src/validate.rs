@@ -0,0 +1,320 @@
+//! checking configs and templates for problems before running them
+//!
+//! Validation collects every problem it finds instead of stopping at the first one.
+//! Each problem is reported with a JSON path to the offending value, e.g. `$.transforms[2].AffineTransform.weight`.
+//!
+//! ```rust
+//! use barnsley::presets::Preset;
+//!
+//! let mut config = Preset::BarnsleyFern.config();
+//! config.image_settings.width = 0;
+//! let problems = config.validate();
+//! assert_eq!(problems[0].path, "$.image_settings.width");
+//! ```
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use image::ImageFormat;
+use serde_json::Value;
+use crate::config::{Config, EvaluationSettings, ImageSettings, PreviewInterval};
+use crate::image::OutputFormat;
+use crate::schema::transform_names;
+use crate::template::Template;
+use crate::transform::{Transform, Transformable};
+use crate::transition::Transitions;
+
+/// How close to zero `ad - bc` may get before a Moebius transform counts as singular
+pub const SINGULAR_TOLERANCE: f32 = 1e-6;
+
+/// A single problem found while validating
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// JSON path to the value with the problem
+    pub path: String,
+    /// description of the problem
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { path: path.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Config {
+    /// Check the config for problems that would make running it fail or produce an empty image.
+    /// An empty result means no problems were found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut problems = vec![];
+        check_image_settings(&self.image_settings, &mut problems);
+        check_evaluation_settings(&self.evaluation_settings, &mut problems);
+        if self.image_settings.tile_rows.is_some() && self.evaluation_settings.target.is_some() {
+            problems.push(Diagnostic::new("$.evaluation_settings.target",
+                                          "tiled renders draw a fixed number of points, a target can not be used with tile_rows"));
+        }
+        check_transforms(&self.transforms, &mut problems);
+        if let Some(transitions) = &self.transitions {
+            let weights: Vec<f32> = self.transforms.iter().map(|t| t.get_weight()).collect();
+            // transitions are written externally tagged, e.g. {"AllowedNext": [[1], [0]]}
+            let variant = match transitions {
+                Transitions::Matrix(_) => "Matrix",
+                Transitions::AllowedNext(_) => "AllowedNext"
+            };
+            for error in transitions.validate(&weights) {
+                let path = match error.row {
+                    Some(row) => format!("$.transitions.{}[{}]", variant, row),
+                    None => format!("$.transitions.{}", variant)
+                };
+                problems.push(Diagnostic::new(path, error.message));
+            }
+        }
+        if self.labels.len() > self.transforms.len() {
+            problems.push(Diagnostic::new("$.labels", format!("there are {} labels but only {} transforms",
+                                                               self.labels.len(), self.transforms.len())));
+        }
+        problems
+    }
+}
+
+impl Template {
+    /// Check the template for problems that would make generating or running a config from it fail.
+    /// An empty result means no problems were found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut problems = vec![];
+        check_image_settings(&self.image_settings, &mut problems);
+        check_evaluation_settings(&self.evaluation_settings, &mut problems);
+        if self.random_transforms.is_empty() {
+            problems.push(Diagnostic::new("$.random_transforms", "there must be at least one transform"));
+        }
+        let names = transform_names();
+        for (i, name) in self.random_transforms.iter().enumerate() {
+            if !names.contains(name) {
+                problems.push(Diagnostic::new(format!("$.random_transforms[{}]", i),
+                    format!("unknown transform {}, expected one of {}", name, names.join(", "))));
+            }
+        }
+        problems
+    }
+}
+
+fn check_image_settings(settings: &ImageSettings, problems: &mut Vec<Diagnostic>) {
+    if settings.width == 0 {
+        problems.push(Diagnostic::new("$.image_settings.width", "width must be greater than zero"));
+    }
+    if settings.height == 0 {
+        problems.push(Diagnostic::new("$.image_settings.height", "height must be greater than zero"));
+    }
+    if let Some(message) = check_output_path(&settings.path, settings.output_format()) {
+        problems.push(Diagnostic::new("$.image_settings.path", message));
+    }
+    match settings.tile_rows {
+        Some(0) => problems.push(Diagnostic::new("$.image_settings.tile_rows", "tile_rows must be greater than zero")),
+        Some(_) if !matches!(settings.output_format(), OutputFormat::EightBit | OutputFormat::Png16)
+            || !settings.path.to_lowercase().ends_with(".png") => problems.push(Diagnostic::new(
+            "$.image_settings.tile_rows", "tiled renders can only be saved as PNG")),
+        _ => {}
+    }
+    if settings.transparent {
+        if settings.background.is_some() {
+            problems.push(Diagnostic::new("$.image_settings.background", "a transparent image can not have a background"));
+        }
+        let format = settings.output_format();
+        let extension = Path::new(&settings.path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        if format.is_float() || format == OutputFormat::Accumulation || extension == "jpg" || extension == "jpeg" {
+            problems.push(Diagnostic::new("$.image_settings.transparent",
+                                          format!("{} can not be saved with an alpha channel", settings.path)));
+        }
+    }
+    match settings.preview.as_ref().map(|p| p.every) {
+        Some(PreviewInterval::Points(0)) => problems.push(Diagnostic::new(
+            "$.image_settings.preview.every.Points", "previews must be written at least every point")),
+        Some(PreviewInterval::Seconds(seconds)) if !(seconds >= 0.0 && seconds.is_finite()) => problems.push(Diagnostic::new(
+            "$.image_settings.preview.every.Seconds", "seconds between previews must be a finite number, zero or more")),
+        _ => {}
+    }
+}
+
+/// Check that an image can be written to `path` in `format`, returning the problem if not
+fn check_output_path(path: &str, format: OutputFormat) -> Option<String> {
+    let path = Path::new(path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let extension_fits = match format {
+        OutputFormat::EightBit => ImageFormat::from_path(path).is_ok(),
+        OutputFormat::Png16 => extension == "png",
+        OutputFormat::Tiff16 => extension == "tif" || extension == "tiff",
+        OutputFormat::Exr => extension == "exr",
+        OutputFormat::Pfm => extension == "pfm",
+        OutputFormat::Accumulation => extension == "acc"
+    };
+    if !extension_fits {
+        return Some(format!("{} does not have a file extension that fits the {:?} output format", path.display(), format));
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+    match directory.metadata() {
+        Err(_) => Some(format!("directory {} does not exist", directory.display())),
+        Ok(metadata) if !metadata.is_dir() => Some(format!("{} is not a directory", directory.display())),
+        Ok(_) if !can_create_file_in(directory) => Some(format!("directory {} is not writable", directory.display())),
+        Ok(_) => match path.metadata() {
+            Ok(metadata) if metadata.is_dir() => Some(format!("{} is a directory", path.display())),
+            // opening to append checks the permissions of the current user without changing the file
+            Ok(_) if OpenOptions::new().append(true).open(path).is_err() => Some(format!("{} is not writable", path.display())),
+            _ => None
+        }
+    }
+}
+
+/// Whether the current user can create files in `directory`, found by creating and removing one. 
+/// The permission bits alone do not say, as they depend on who owns the directory. 
+fn can_create_file_in(directory: &Path) -> bool {
+    let probe = directory.join(format!(".barnsley_validate_{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(_) => false
+    }
+}
+
+fn check_evaluation_settings(settings: &EvaluationSettings, problems: &mut Vec<Diagnostic>) {
+    if settings.num_iterations == 0 {
+        problems.push(Diagnostic::new("$.evaluation_settings.num_iterations", "num_iterations must be greater than zero"));
+    }
+    if settings.num_points == 0 {
+        problems.push(Diagnostic::new("$.evaluation_settings.num_points", "num_points must be greater than zero"));
+    }
+    if settings.checkpoint_every == Some(0) {
+        problems.push(Diagnostic::new("$.evaluation_settings.checkpoint_every", "checkpoint_every must be greater than zero"));
+    }
+    if let Some(target) = settings.target {
+        let targets = [("samples_per_lit_pixel", target.samples_per_lit_pixel), ("relative_change", target.relative_change),
+                       ("time_budget", target.time_budget)];
+        if target.is_empty() {
+            problems.push(Diagnostic::new("$.evaluation_settings.target", "target needs at least one condition to stop at"));
+        }
+        for (name, value) in targets {
+            if value.is_some_and(|v| !(v >= 0.0 && v.is_finite())) {
+                problems.push(Diagnostic::new(format!("$.evaluation_settings.target.{}", name),
+                                              format!("{} must be a finite number, zero or more", name)));
+            }
+        }
+    }
+}
+
+fn check_transforms(transforms: &[Transform], problems: &mut Vec<Diagnostic>) {
+    if transforms.is_empty() {
+        problems.push(Diagnostic::new("$.transforms", "there must be at least one transform"));
+        return
+    }
+
+    for (i, transform) in transforms.iter().enumerate() {
+        let path = format!("$.transforms[{}].{}", i, transform.get_name());
+
+        // non-finite floats serialize as null, so walking the serialized transform finds them all
+        let value = serde_json::to_value(transform).unwrap();
+        check_finite(&value[transform.get_name()], &path, problems);
+
+        if transform.get_weight() < 0.0 {
+            problems.push(Diagnostic::new(format!("{}.weight", path), "weight must not be negative"));
+        }
+
+        if let Transform::MoebiusTransform(t) = transform {
+            let determinant = t.a * t.d - t.b * t.c;
+            if determinant.norm() < SINGULAR_TOLERANCE {
+                problems.push(Diagnostic::new(path, "transform is singular, ad - bc is zero"));
+            }
+        }
+    }
+
+    let total_weight: f32 = transforms.iter().map(|t| t.get_weight()).filter(|w| *w > 0.0).sum();
+    if total_weight == 0.0 {
+        problems.push(Diagnostic::new("$.transforms", "at least one transform must have a positive weight"));
+    }
+}
+
+fn check_finite(value: &Value, path: &str, problems: &mut Vec<Diagnostic>) {
+    match value {
+        Value::Null => problems.push(Diagnostic::new(path, "value is NaN or infinite")),
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                check_finite(item, &format!("{}[{}]", path, i), problems);
+            }
+        },
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                check_finite(field, &format!("{}.{}", path, name), problems);
+            }
+        },
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::complex::Complex32;
+    use crate::presets::Preset;
+    use crate::transform::{AffineTransform, MoebiusTransform};
+    use crate::transition::Transitions;
+    use crate::util::Color;
+
+    #[test]
+    fn test_valid_config_has_no_problems() {
+        assert!(Preset::SierpinskiTriangle.config().validate().is_empty());
+    }
+
+    #[test]
+    fn test_checks_output_directory_by_writing_to_it() {
+        let directory = crate::util::test_path("validate");
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut config = Preset::SierpinskiTriangle.config();
+        config.image_settings.path = directory.join("out.png").to_str().unwrap().to_string();
+        assert!(config.validate().is_empty());
+        // the file written to check the directory is removed again
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+        std::fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let mut config = Preset::SierpinskiTriangle.config();
+        config.image_settings.height = 0;
+        config.image_settings.path = "missing_directory/out.png".to_string();
+        let color = Color{r: 1.0, g: 1.0, b: 1.0};
+        config.transforms = vec![
+            AffineTransform::new(f32::NAN, 0.0, 0.0, 0.5, 0.0, 0.0, color, 0.0).into(),
+            MoebiusTransform::new(Complex32::new(1.0, 0.0), Complex32::new(2.0, 0.0),
+                                  Complex32::new(1.0, 0.0), Complex32::new(2.0, 0.0), color, -1.0).into(),
+        ];
+
+        let paths: Vec<String> = config.validate().into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, vec![
+            "$.image_settings.height",
+            "$.image_settings.path",
+            "$.transforms[0].AffineTransform.a",
+            "$.transforms[1].MoebiusTransform.weight",
+            "$.transforms[1].MoebiusTransform",
+            "$.transforms",
+        ]);
+    }
+
+    #[test]
+    fn test_reports_unreachable_transition_row() {
+        let mut config = Preset::SierpinskiTriangle.config();
+        let color = Color{r: 1.0, g: 1.0, b: 1.0};
+        config.transforms = vec![
+            AffineTransform::new(0.5, 0.0, 0.0, 0.5, 0.0, 0.0, color, 1.0).into(),
+            AffineTransform::new(0.5, 0.0, 0.0, 0.5, 0.5, 0.0, color, 0.0).into(),
+            AffineTransform::new(0.5, 0.0, 0.0, 0.5, 0.0, 0.5, color, 1.0).into(),
+        ];
+        config.transitions = Some(Transitions::AllowedNext(vec![vec![1], vec![0], vec![5]]));
+
+        let paths: Vec<String> = config.validate().into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, vec!["$.transitions.AllowedNext[0]", "$.transitions.AllowedNext[2]"]);
+    }
+}